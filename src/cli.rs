@@ -1,15 +1,33 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
 
 use crate::commands::*;
 use crate::errors::Error;
+use crate::utils::cache::set_cache_dir_override;
+use crate::utils::config::UserConfig;
+use crate::utils::workspace::set_wait_for_lock;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None, arg_required_else_help(true))]
 pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
     #[command(subcommand)]
     pub command: Option<Command>,
 }
 
+#[derive(Args, Debug)]
+pub struct GlobalArgs {
+    #[clap(long, global = true, help = "Directory of the global cache")]
+    pub cache_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        global = true,
+        help = "Wait for other crane processes using the workspace instead of failing"
+    )]
+    pub wait: bool,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Command {
     #[clap(
@@ -22,14 +40,48 @@ pub enum Command {
         long_about = "Show status of all the dependencies in current project"
     )]
     Status(status::CommandArgs),
+    #[clap(
+        about = "Manage the global cache",
+        long_about = "List, garbage collect, verify and prune the repositories in the global cache"
+    )]
+    Cache(cache::CommandArgs),
+    #[clap(
+        about = "Stop depending on the global cache",
+        long_about = "Copy the objects borrowed from the global cache into every repository of current project"
+    )]
+    Dissociate(dissociate::CommandArgs),
+    #[clap(
+        about = "Diagnose current project",
+        long_about = "Show the alternates of every repository in current project and optionally fix them"
+    )]
+    Doctor(doctor::CommandArgs),
+    #[clap(
+        about = "Check the pinned commits of current project",
+        long_about = "Fetch every dependency of current project into the global cache and check that its pinned commit is reachable from its branch, without checking anything out"
+    )]
+    Check(check::CommandArgs),
+    #[clap(
+        about = "Remove components dropped from the configuration",
+        long_about = "Remove the components of the last sync which are not declared in current project any more, keeping those with local changes or unpushed commits"
+    )]
+    Prune(prune::CommandArgs),
     #[clap(about = "Show current version")]
     Version,
 }
 
-pub async fn run_command(cmd: &Command) -> Result<(), Error> {
+pub async fn run_command(global: &GlobalArgs, cmd: &Command) -> Result<(), Error> {
+    UserConfig::set_instance(UserConfig::load()?);
+    set_cache_dir_override(global.cache_dir.clone());
+    set_wait_for_lock(global.wait);
+
     match cmd {
         Command::Sync(args) => sync::run(args).await,
         Command::Status(args) => status::run(args).await,
+        Command::Cache(args) => cache::run(args),
+        Command::Dissociate(args) => dissociate::run(args).await,
+        Command::Doctor(args) => doctor::run(args).await,
+        Command::Check(args) => check::run(args).await,
+        Command::Prune(args) => prune::run(args).await,
         Command::Version => version::run(),
     }
 }
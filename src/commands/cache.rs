@@ -0,0 +1,212 @@
+use clap::{Args, Subcommand};
+use colored::Colorize;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use crate::errors::Error;
+use crate::utils::cache::{
+    forget_missing_workspace_repos, is_registered, list_cache_repos, lock_cache_repo,
+    workspace_repos_using, CacheRepo,
+};
+use crate::utils::process::Command;
+
+#[derive(Args, Debug)]
+pub struct CommandArgs {
+    #[command(subcommand)]
+    pub command: CacheCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    #[clap(about = "List the repositories in the global cache")]
+    List,
+    #[clap(about = "Repack and prune every repository in the global cache")]
+    Gc,
+    #[clap(about = "Check the integrity of every repository in the global cache")]
+    Verify,
+    #[clap(about = "Remove the repositories not fetched for a while from the global cache")]
+    Prune(PruneArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct PruneArgs {
+    #[clap(
+        long,
+        value_parser = parse_duration,
+        help = "Remove repositories not fetched for this long, e.g. 30d, 12h, 2w"
+    )]
+    pub older_than: Duration,
+}
+
+// Parse a duration like "90s", "30m", "12h", "7d" or "2w"
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration \"{}\"", s))?;
+    let seconds = match unit {
+        "s" | "" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid unit of duration \"{}\"", s)),
+    };
+    Ok(Duration::from_secs(value * seconds))
+}
+
+fn format_size(size: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+fn format_age(time: Option<SystemTime>) -> String {
+    let age = match time.and_then(|t| t.elapsed().ok()) {
+        Some(age) => age.as_secs(),
+        None => return String::from("unknown"),
+    };
+    match age {
+        a if a < 60 => format!("{}s ago", a),
+        a if a < 60 * 60 => format!("{}m ago", a / 60),
+        a if a < 24 * 60 * 60 => format!("{}h ago", a / 60 / 60),
+        a => format!("{}d ago", a / 24 / 60 / 60),
+    }
+}
+
+fn display_url(repo: &CacheRepo) -> String {
+    repo.url
+        .clone()
+        .unwrap_or_else(|| format!("(unknown) {}", repo.path.display()))
+}
+
+fn list() -> Result<(), Error> {
+    let repos = list_cache_repos()?;
+    if repos.is_empty() {
+        println!("The cache is empty");
+        return Ok(());
+    }
+    println!("{:>10}  {:>12}  URL", "SIZE", "LAST FETCH");
+    for repo in &repos {
+        println!(
+            "{:>10}  {:>12}  {}",
+            format_size(repo.size),
+            format_age(repo.last_fetch),
+            display_url(repo)
+        );
+    }
+    let total: u64 = repos.iter().map(|r| r.size).sum();
+    println!(
+        "{} repositories, {} in total",
+        repos.len(),
+        format_size(total)
+    );
+    Ok(())
+}
+
+// Run a git command in every cache repository while holding its lock
+fn run_git_in_cache_repos(action: &str, args: &[&str]) -> Result<(), Error> {
+    let mut failed = vec![];
+    for repo in list_cache_repos()? {
+        let url = display_url(&repo);
+        let _lock = lock_cache_repo(&repo.path, &url)?;
+        let mut cmd = Command::new("git");
+        for arg in args {
+            cmd.arg(arg);
+        }
+        let output = cmd.current_dir(&repo.path).output()?;
+        if output.status.success() {
+            println!("{} {}", action.green(), url);
+        } else {
+            println!("{} {}", "failed".red(), url);
+            print!("{}", String::from_utf8_lossy(&output.stderr));
+            failed.push(url);
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::new(format!(
+            "{} cache repositories failed: {}",
+            failed.len(),
+            failed.join(", ")
+        )))
+    }
+}
+
+fn prune(args: &PruneArgs) -> Result<(), Error> {
+    forget_missing_workspace_repos()?;
+
+    for repo in list_cache_repos()? {
+        let expired = repo
+            .last_fetch
+            .and_then(|t| t.elapsed().ok())
+            .is_none_or(|age| age > args.older_than);
+        if !expired {
+            continue;
+        }
+
+        let url = display_url(&repo);
+        if !is_registered(&repo.path) {
+            println!(
+                "{} {}, it might be used by workspaces synced by an older crane",
+                "kept".yellow(),
+                url
+            );
+            continue;
+        }
+        let users = workspace_repos_using(&repo.path)?;
+        if !users.is_empty() {
+            println!("{} {}, still used by:", "kept".yellow(), url);
+            for user in users {
+                println!("    {}", user.display());
+            }
+            continue;
+        }
+
+        let _lock = lock_cache_repo(&repo.path, &url)?;
+        fs::remove_dir_all(&repo.path)?;
+        println!("{} {} ({})", "removed".green(), url, format_size(repo.size));
+    }
+    Ok(())
+}
+
+pub fn run(args: &CommandArgs) -> Result<(), Error> {
+    match &args.command {
+        CacheCommand::List => list(),
+        CacheCommand::Gc => run_git_in_cache_repos("packed", &["gc", "--prune=now", "--quiet"]),
+        CacheCommand::Verify => run_git_in_cache_repos("verified", &["fsck", "--no-progress"]),
+        CacheCommand::Prune(prune_args) => prune(prune_args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 3600)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 86400)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::from_secs(14 * 86400)));
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("3y").is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512.0 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
@@ -0,0 +1,63 @@
+use clap::Args;
+use git2::Repository;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::components::component::visit_root_solution;
+use crate::constants::CRANE_FILE;
+use crate::errors::Error;
+use crate::utils::config::UserConfig;
+use crate::utils::git_utils;
+use crate::utils::workspace::{LockMode, WorkspaceLock};
+use crate::visitors::check_visitor::CheckVisitor;
+
+#[derive(Args, Debug)]
+pub struct CommandArgs {
+    pub dir: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Only warn when a pinned commit is not reachable from the branch of its component"
+    )]
+    pub allow_unreachable: bool,
+}
+
+pub async fn run(args: &CommandArgs) -> Result<(), Error> {
+    let root_dir = args.dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let abs_root_dir = std::fs::canonicalize(&root_dir)?;
+    let _lock = WorkspaceLock::acquire(&abs_root_dir, LockMode::Shared)?;
+    UserConfig::apply_workspace(&abs_root_dir)?;
+
+    let repo = Repository::open(&abs_root_dir)?;
+    let url = repo
+        .find_remote("origin")
+        .ok()
+        .and_then(|remote| remote.url().map(|u| u.to_string()))
+        .unwrap_or_default();
+    let head = repo.head()?;
+    // The solution itself is checked like sync updates it, by branch if it is on one
+    let branch = if head.is_branch() {
+        head.shorthand().map(|b| b.to_string())
+    } else {
+        git_utils::find_legacy_branch(&repo)
+    };
+    let commit = match branch {
+        Some(_) => None,
+        None => head.target().map(|c| c.to_string()),
+    };
+
+    let failures = AtomicUsize::new(0);
+    visit_root_solution(
+        &CheckVisitor::new(args.allow_unreachable, &failures),
+        &abs_root_dir,
+        url,
+        branch,
+        commit,
+        Some(CRANE_FILE.to_string()),
+    )
+    .await?;
+
+    match failures.load(Ordering::SeqCst) {
+        0 => Ok(()),
+        n => Err(Error::new(format!("{} components failed the check", n))),
+    }
+}
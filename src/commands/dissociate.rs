@@ -0,0 +1,39 @@
+use clap::Args;
+use git2::Repository;
+use std::path::PathBuf;
+
+use crate::components::component::visit_root_solution;
+use crate::constants::CRANE_FILE;
+use crate::errors::Error;
+use crate::utils::workspace::{LockMode, WorkspaceLock};
+use crate::visitors::dissociate_visitor::DissociateVisitor;
+
+#[derive(Args, Debug)]
+pub struct CommandArgs {
+    pub dir: Option<PathBuf>,
+}
+
+pub async fn run(args: &CommandArgs) -> Result<(), Error> {
+    let root_dir = args.dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let abs_root_dir = std::fs::canonicalize(&root_dir)?;
+    let _lock = WorkspaceLock::acquire(&abs_root_dir, LockMode::Exclusive)?;
+
+    let repo = Repository::open(&abs_root_dir)?;
+    let url = repo
+        .find_remote("origin")
+        .ok()
+        .and_then(|remote| remote.url().map(|u| u.to_string()))
+        .unwrap_or_default();
+    let head = repo.head()?;
+
+    visit_root_solution(
+        &DissociateVisitor::new(),
+        &abs_root_dir,
+        url,
+        head.shorthand().map(|b| b.to_string()),
+        head.target().map(|c| c.to_string()),
+        Some(CRANE_FILE.to_string()),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use clap::Args;
+use git2::Repository;
+use std::path::PathBuf;
+
+use crate::components::component::visit_root_solution;
+use crate::constants::CRANE_FILE;
+use crate::errors::Error;
+use crate::utils::workspace::{LockMode, WorkspaceLock};
+use crate::visitors::doctor_visitor::DoctorVisitor;
+
+#[derive(Args, Debug)]
+pub struct CommandArgs {
+    pub dir: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Remove duplicate alternates and re-point the missing ones to the current cache dir"
+    )]
+    pub fix: bool,
+}
+
+pub async fn run(args: &CommandArgs) -> Result<(), Error> {
+    let root_dir = args.dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let abs_root_dir = std::fs::canonicalize(&root_dir)?;
+    let lock_mode = if args.fix {
+        LockMode::Exclusive
+    } else {
+        LockMode::Shared
+    };
+    let _lock = WorkspaceLock::acquire(&abs_root_dir, lock_mode)?;
+
+    let repo = Repository::open(&abs_root_dir)?;
+    let url = repo
+        .find_remote("origin")
+        .ok()
+        .and_then(|remote| remote.url().map(|u| u.to_string()))
+        .unwrap_or_default();
+    let head = repo.head()?;
+
+    visit_root_solution(
+        &DoctorVisitor::new(args.fix),
+        &abs_root_dir,
+        url,
+        head.shorthand().map(|b| b.to_string()),
+        head.target().map(|c| c.to_string()),
+        Some(CRANE_FILE.to_string()),
+    )
+    .await?;
+    Ok(())
+}
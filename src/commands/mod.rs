@@ -1,3 +1,8 @@
+pub mod cache;
+pub mod check;
+pub mod dissociate;
+pub mod doctor;
+pub mod prune;
 pub mod status;
 pub mod sync;
 pub mod version;
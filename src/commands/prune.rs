@@ -0,0 +1,150 @@
+use clap::Args;
+use colored::Colorize;
+use git2::{Repository, StatusOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::components::component::visit_root_solution;
+use crate::constants::CRANE_FILE;
+use crate::errors::Error;
+use crate::utils::cache::cache_repo_dir;
+use crate::utils::git_utils;
+use crate::utils::workspace::{LockMode, WorkspaceLock, WorkspaceState};
+use crate::visitors::prune_visitor::PruneVisitor;
+
+#[derive(Args, Debug)]
+pub struct CommandArgs {
+    pub dir: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Remove components even if they have local changes or unpushed commits"
+    )]
+    pub force: bool,
+}
+
+// Why a left over component can not be removed without losing work
+fn unsaved_work(url: &str, target_dir: &Path) -> Result<Option<String>, Error> {
+    let repo = match Repository::open(target_dir) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(Some(String::from("not a git repository"))),
+    };
+    // Untracked files might be work too, ignored ones are build outputs
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true).include_ignored(false);
+    if !repo.statuses(Some(&mut opts))?.is_empty() {
+        return Ok(Some(String::from("local changes")));
+    }
+    if repo.find_reference("refs/stash").is_ok() {
+        return Ok(Some(String::from("stashed changes")));
+    }
+    // Whatever is in the cache was fetched from the remote
+    let cache_repo = cache_repo_dir(url)
+        .ok()
+        .and_then(|dir| Repository::open(dir).ok());
+    let unpushed = git_utils::find_unpushed_refs(&repo, cache_repo.as_ref())?;
+    if !unpushed.is_empty() {
+        return Ok(Some(format!("unpushed commits on {}", unpushed.join(", "))));
+    }
+    Ok(None)
+}
+
+// A component of the last sync which is not declared any more
+#[derive(Debug, Clone)]
+pub struct Leftover {
+    pub url: String,
+    pub path: PathBuf,
+    // Why it is kept, it is removed otherwise
+    pub kept: Option<String>,
+}
+
+// Remove the components of the last sync which are not declared any more. The walk recording
+// the declared components in `state` must be complete. A dry run only returns what would be
+// removed and kept.
+pub fn prune_leftovers(
+    state: &mut WorkspaceState,
+    force: bool,
+    dry_run: bool,
+) -> Result<Vec<Leftover>, Error> {
+    let root_dir = state.root_dir().to_path_buf();
+    let declared: Vec<PathBuf> = state.synced().into_iter().map(|(_, path)| path).collect();
+    let mut leftovers = state.leftovers();
+    // Nested components first, a component is kept if anything in it is
+    leftovers.sort_by(|a, b| b.1.cmp(&a.1));
+    let mut kept: Vec<PathBuf> = vec![];
+    let mut pruned = vec![];
+
+    for (url, target_dir) in leftovers {
+        let path = target_dir.strip_prefix(&root_dir).unwrap_or(&target_dir);
+        if target_dir == root_dir {
+            state.record(&url, &target_dir)?;
+            continue;
+        }
+        if !target_dir.exists() {
+            state.forget(&target_dir);
+            continue;
+        }
+        let reason = if declared.iter().any(|d| d.starts_with(&target_dir)) {
+            Some(String::from("contains declared components"))
+        } else if kept.iter().any(|k| k.starts_with(&target_dir)) {
+            Some(String::from("contains kept components"))
+        } else if force {
+            None
+        } else {
+            unsaved_work(&url, &target_dir)?
+        };
+
+        match &reason {
+            Some(reason) => {
+                if !dry_run {
+                    println!("Kept {}: {}", path.display(), reason.yellow());
+                }
+                state.record(&url, &target_dir)?;
+                kept.push(target_dir.clone());
+            }
+            None => {
+                if !dry_run {
+                    fs::remove_dir_all(&target_dir)?;
+                    println!("Removed {}", path.display());
+                }
+                state.forget(&target_dir);
+            }
+        }
+        pruned.push(Leftover {
+            url,
+            path: target_dir,
+            kept: reason,
+        });
+    }
+    if !kept.is_empty() && !force && !dry_run {
+        println!("Pass --force to remove the kept components anyway");
+    }
+    Ok(pruned)
+}
+
+pub async fn run(args: &CommandArgs) -> Result<(), Error> {
+    let root_dir = args.dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let abs_root_dir = std::fs::canonicalize(&root_dir)?;
+    let _lock = WorkspaceLock::acquire(&abs_root_dir, LockMode::Exclusive)?;
+
+    let repo = Repository::open(&abs_root_dir)?;
+    let url = repo
+        .find_remote("origin")
+        .ok()
+        .and_then(|remote| remote.url().map(|u| u.to_string()))
+        .unwrap_or_default();
+    let head = repo.head()?;
+
+    let mut state = WorkspaceState::load(&abs_root_dir)?;
+    visit_root_solution(
+        &PruneVisitor::new(&state),
+        &abs_root_dir,
+        url,
+        head.shorthand().map(|b| b.to_string()),
+        head.target().map(|c| c.to_string()),
+        Some(CRANE_FILE.to_string()),
+    )
+    .await?;
+
+    prune_leftovers(&mut state, args.force, false)?;
+    state.save(true)
+}
@@ -11,6 +11,8 @@ use std::path::PathBuf;
 
 use crate::components::component::ComponentArena;
 use crate::constants::CRANE_FILE;
+use crate::utils::alternates;
+use crate::utils::workspace::{LockMode, WorkspaceLock};
 use crate::visitors::status_visitor::StatusVisitor;
 
 const TAB_SIZE: usize = 2;
@@ -213,6 +215,21 @@ async fn show_status(root_dir: &PathBuf, mut output: impl std::io::Write) -> Res
         let head_info = head.shorthand().unwrap_or("unknown");
         writeln!(output, " ({})", head_info)?;
 
+        // The objects borrowed from a removed cache repository are gone as well
+        for missing in alternates::missing(&comp.target_dir).unwrap_or_default() {
+            writeln_with_depth(
+                &mut output,
+                depth + 1,
+                format!(
+                    "warning: objects are borrowed from {} which does not exist anymore",
+                    missing.display()
+                )
+                .yellow()
+                .to_string()
+                .as_str(),
+            )?;
+        }
+
         // Directories of children should not be seen
         let mut children_names: Vec<String> = vec![];
         if !comp.children.is_empty() {
@@ -236,11 +253,9 @@ async fn show_status(root_dir: &PathBuf, mut output: impl std::io::Write) -> Res
 pub async fn run(args: &CommandArgs) -> Result<(), Error> {
     println!("{:?}", args);
 
-    if let Some(target_dir) = &args.dir {
-        show_status(target_dir, std::io::stdout()).await
-    } else {
-        show_status(&PathBuf::from("."), std::io::stdout()).await
-    }
+    let target_dir = args.dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let _lock = WorkspaceLock::acquire(&target_dir, LockMode::Shared)?;
+    show_status(&target_dir, std::io::stdout()).await
 }
 
 #[cfg(test)]
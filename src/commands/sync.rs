@@ -4,15 +4,28 @@ use clap::Args;
 use git2::Repository;
 
 use std::env;
+use std::fs;
 use std::path::{Path, PathBuf};
 
+use crate::commands::prune::{prune_leftovers, Leftover};
 use crate::components::component::visit_root_solution;
 use crate::constants::CRANE_FILE;
-use crate::visitors::sync_visitor::ComponentSyncVisitor;
+use crate::utils::config::UserConfig;
+use crate::utils::interrupt;
+use crate::utils::selector::Selection;
+use crate::utils::workspace::{LockMode, WorkspaceLock, WorkspaceState};
+use crate::visitors::sync_visitor::{
+    ComponentPlan, ComponentSyncVisitor, PlanFormat, RemoteHeads, SyncAction, SyncOptions,
+    SyncReport, SyncStrategy,
+};
 
 #[derive(Args, Debug)]
 pub struct CommandArgs {
-    pub dir: Option<PathBuf>,
+    #[clap(
+        value_name = "DIR_OR_COMPONENT",
+        help = "Directory of the solution followed by the components to sync by name, path prefix or glob. In the root of a solution, they are all components unless the first one is a directory outside of it."
+    )]
+    pub targets: Vec<String>,
     #[clap(long)]
     pub url: Option<String>,
     #[clap(long)]
@@ -21,6 +34,92 @@ pub struct CommandArgs {
     pub commit: Option<String>,
     #[clap(long)]
     pub remote: Option<String>,
+    #[clap(long, help = "Fetch directly without going through the global cache")]
+    pub no_cache: bool,
+    #[clap(
+        long,
+        help = "Copy the objects borrowed from the global cache so that the workspace does not depend on it"
+    )]
+    pub dissociate: bool,
+    #[clap(
+        long,
+        help = "Check out even if local changes or unpushed commits would be lost"
+    )]
+    pub force: bool,
+    #[clap(
+        long,
+        conflicts_with = "force",
+        help = "Stash local changes before checking out and re-apply them afterwards"
+    )]
+    pub autostash: bool,
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = SyncStrategy::Reset,
+        help = "How to update a branch with local commits"
+    )]
+    pub strategy: SyncStrategy,
+    #[clap(
+        long,
+        help = "Only warn when a pinned commit is not reachable from the branch of its component"
+    )]
+    pub allow_unreachable: bool,
+    #[clap(
+        long,
+        help = "Print what would be done to every component without changing the workspace"
+    )]
+    pub dry_run: bool,
+    #[clap(
+        long,
+        help = "Never fetch, check out the revisions from the global cache and the workspace only"
+    )]
+    pub offline: bool,
+    #[clap(
+        long,
+        help = "Fail instead of updating the remote of a component whose url changed"
+    )]
+    pub strict_urls: bool,
+    #[clap(
+        long,
+        help = "Remove the components dropped from the configuration, see crane prune"
+    )]
+    pub prune: bool,
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = PlanFormat::Text,
+        requires = "dry_run",
+        help = "Format of the plan printed by --dry-run"
+    )]
+    pub format: PlanFormat,
+}
+
+// What pruning does to a component dropped from the configuration, it is named by its path
+fn leftover_plan(root_dir: &Path, leftover: Leftover) -> ComponentPlan {
+    let head = Repository::open(&leftover.path)
+        .ok()
+        .and_then(|repo| repo.head().ok()?.target())
+        .map(|oid| oid.to_string());
+    ComponentPlan {
+        name: leftover
+            .path
+            .strip_prefix(root_dir)
+            .unwrap_or(&leftover.path)
+            .display()
+            .to_string(),
+        path: leftover.path,
+        url: leftover.url,
+        action: match leftover.kept {
+            Some(_) => SyncAction::Keep,
+            None => SyncAction::Remove,
+        },
+        from: head.clone(),
+        to: head.unwrap_or_default(),
+        stash: false,
+        reason: leftover.kept,
+        previous_url: None,
+        moved_from: None,
+    }
 }
 
 async fn do_sync(
@@ -28,7 +127,9 @@ async fn do_sync(
     branch: Option<String>,
     commit: Option<String>,
     root_dir: Option<PathBuf>,
+    selectors: &[String],
     remote_name: &str,
+    options: SyncOptions,
 ) -> Result<(), Error> {
     let url_str;
     let abs_root_dir;
@@ -64,10 +165,11 @@ async fn do_sync(
             })?;
             abs_root_dir = env::current_dir()?.join(repo_name);
             url_str = u.clone();
-            target_branch = target_branch.or(git_utils::get_remote_default_branch(
-                &url.unwrap(),
-                Some(remote_name),
-            ));
+            // The default branch is only known to the remote
+            if !options.offline {
+                target_branch =
+                    target_branch.or(git_utils::get_remote_default_branch(&url.unwrap()));
+            }
         }
         (None, _) => {
             match root_dir {
@@ -80,7 +182,7 @@ async fn do_sync(
             }
             let repo = Repository::open(&abs_root_dir)?;
             url_str = repo
-                .find_remote("origin")?
+                .find_remote(remote_name)?
                 .url()
                 .ok_or(Error {
                     message: "Remote url is not set".to_string(),
@@ -91,7 +193,12 @@ async fn do_sync(
             target_branch = target_branch.or_else(|| {
                 let repo = Repository::open(&abs_root_dir).ok()?;
                 let head = repo.head().ok()?;
-                let b = head.shorthand().map(|b| b.to_string());
+                let b = if head.is_branch() {
+                    head.shorthand().map(|b| b.to_string())
+                } else {
+                    // Workspaces synced by older versions have a detached HEAD
+                    git_utils::find_legacy_branch(&repo)
+                };
 
                 // If the target_branch can not be found, we try to set the head commit
                 if b.is_none() {
@@ -102,10 +209,35 @@ async fn do_sync(
         }
     }
 
-    println!("Sync solution to {}", abs_root_dir.display());
+    // A dry run leaves the workspace untouched
+    let lock_mode = if options.dry_run {
+        LockMode::Shared
+    } else {
+        LockMode::Exclusive
+    };
+    // A clone is locked from the start, its root is created for that
+    let cloning = !options.dry_run && !abs_root_dir.exists();
+    if cloning {
+        fs::create_dir_all(&abs_root_dir)?;
+    }
+    let lock = WorkspaceLock::acquire(&abs_root_dir, lock_mode)?;
+    UserConfig::apply_workspace(&abs_root_dir)?;
+
+    // The plan is the only output of a dry run, which might be parsed
+    if !options.dry_run {
+        println!("Sync solution to {}", abs_root_dir.display());
+    }
 
-    let visitor = ComponentSyncVisitor::new();
-    let _ = visit_root_solution(
+    let report = SyncReport::default();
+    let heads = RemoteHeads::default();
+    let selection = Selection::new(&abs_root_dir, selectors);
+    let mut state = WorkspaceState::load(&abs_root_dir)?;
+    let mut visitor =
+        ComponentSyncVisitor::new(options, remote_name, &report, &heads).track(&state);
+    if !selectors.is_empty() {
+        visitor = visitor.select(&selection);
+    }
+    let walked = visit_root_solution(
         &visitor,
         &abs_root_dir,
         url_str.to_string(),
@@ -113,9 +245,70 @@ async fn do_sync(
         target_commit,
         Some(CRANE_FILE.to_string()),
     )
-    .await?;
+    .await;
+    // Nothing was cloned, the root only has the lock and maybe an empty repository
+    let cloned = Repository::open(&abs_root_dir).is_ok_and(|repo| repo.head().is_ok());
+    if cloning && !cloned {
+        drop(lock);
+        fs::remove_dir_all(&abs_root_dir)?;
+    }
+    let mut pruned = Ok(vec![]);
+    // Removing anything more is not what an interrupted sync should do
+    if walked.is_ok() && options.prune && !interrupt::interrupted() {
+        pruned = prune_leftovers(&mut state, options.force, options.dry_run);
+    }
+    // Components moved or cloned before a failure are recorded too
+    if !options.dry_run {
+        state.save(selectors.is_empty() && walked.is_ok() && !interrupt::interrupted())?;
+    }
+    walked?;
+    let pruned = pruned?;
 
-    Ok(())
+    let untouched = report.untouched();
+    if !untouched.is_empty() {
+        if !options.dry_run {
+            report.print();
+        }
+        return Err(Error::new(format!(
+            "sync interrupted, {} left untouched, run it again to finish",
+            untouched.join(", ")
+        )));
+    }
+
+    let unmatched = selection.unmatched();
+    if !selectors.is_empty() && !unmatched.is_empty() {
+        return Err(Error::new(format!(
+            "no component matches {}",
+            unmatched.join(", ")
+        )));
+    }
+    if options.dry_run {
+        // The previous path of a moved component is not left over
+        let moved: Vec<PathBuf> = report
+            .plans()
+            .into_iter()
+            .filter_map(|plan| plan.moved_from)
+            .collect();
+        for leftover in pruned.into_iter().filter(|l| !moved.contains(&l.path)) {
+            report.record_plan(leftover_plan(&abs_root_dir, leftover));
+        }
+        return report.print_plan(options.plan_format);
+    }
+    report.print();
+    let missing = report.missing();
+    if !missing.is_empty() {
+        return Err(Error::new(format!(
+            "revisions of {} are missing locally, sync once with network access",
+            missing.join(", ")
+        )));
+    }
+    match report.needs_attention() {
+        0 => Ok(()),
+        n => Err(Error::new(format!(
+            "{} components were not updated cleanly, see the summary above",
+            n
+        ))),
+    }
 }
 
 /* Possible usages:
@@ -129,14 +322,62 @@ async fn do_sync(
  *   crane sync <dir> --url https://xxx.git
  * 5. Sync a new solution with url and branch
  *   crane sync --url https://xxx.git --branch main
+ * 6. Sync some components of an existing solution and their nested deps
+ *   crane sync <dir> third_party/llvm 'lib*'
+ *   crane sync third_party/llvm 'lib*' (in the root of the solution)
 */
+// The first target is the directory of the solution, unless it is a component of the solution
+// in the current directory: in its root, a target which is not an existing directory outside of
+// it is a component
+fn split_targets(
+    targets: &[String],
+    url: &Option<String>,
+) -> Result<(Option<PathBuf>, Vec<String>), Error> {
+    let current_dir = env::current_dir()?.canonicalize()?;
+    if url.is_none() && current_dir.join(CRANE_FILE).exists() {
+        let is_solution_dir = |target: &String| match current_dir.join(target).canonicalize() {
+            Ok(dir) => dir.is_dir() && (dir == current_dir || !dir.starts_with(&current_dir)),
+            Err(_) => false,
+        };
+        if !targets.first().is_some_and(is_solution_dir) {
+            return Ok((None, targets.to_vec()));
+        }
+    }
+    match targets.split_first() {
+        Some((dir, selectors)) => Ok((Some(PathBuf::from(dir)), selectors.to_vec())),
+        None => Ok((None, vec![])),
+    }
+}
+
 pub async fn run(args: &CommandArgs) -> Result<(), Error> {
+    let (dir, selectors) = split_targets(&args.targets, &args.url)?;
+    // Only a sync of every component knows which ones were dropped
+    if args.prune && !selectors.is_empty() {
+        return Err(Error::new(String::from(
+            "--prune can not be used when syncing selected components",
+        )));
+    }
+    interrupt::install_handler();
     do_sync(
         args.url.clone(),
         args.branch.clone(),
         args.commit.clone(),
-        args.dir.clone(),
+        dir,
+        &selectors,
         args.remote.clone().unwrap_or("origin".to_string()).as_str(),
+        SyncOptions {
+            no_cache: args.no_cache,
+            dissociate: args.dissociate,
+            force: args.force,
+            autostash: args.autostash,
+            strategy: args.strategy,
+            allow_unreachable: args.allow_unreachable,
+            dry_run: args.dry_run,
+            offline: args.offline,
+            strict_urls: args.strict_urls,
+            prune: args.prune,
+            plan_format: args.format,
+        },
     )
     .await
 }
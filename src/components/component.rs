@@ -5,6 +5,7 @@ use std::time::Duration;
 
 use crate::components::git_dependency::GitDependency;
 use crate::errors::Error;
+use crate::utils::git_url::{is_relative_url, resolve_relative_url};
 use crate::utils::parser;
 use crate::visitors::component_visitor::ComponentVisitor;
 use futures::future::try_join_all;
@@ -185,8 +186,42 @@ pub async fn visit_component<V: ComponentVisitor>(
     }
 }
 
+// Resolve a relative url of a component against the url of its parent
+fn resolve_url(id: ComponentID) -> Result<(), Error> {
+    let arena = ComponentArena::instance();
+    let (url, parent_id) = {
+        let comp = arena.get(id).unwrap();
+        match comp.impl_.as_any().downcast_ref::<GitDependency>() {
+            Some(git) if is_relative_url(&git.url) => (git.url.clone(), comp.parent_id),
+            _ => return Ok(()),
+        }
+    };
+    let parent_url = parent_id
+        .and_then(|parent_id| {
+            let parent = arena.get(parent_id)?;
+            let git = parent.impl_.as_any().downcast_ref::<GitDependency>()?;
+            Some(git.url.clone())
+        })
+        .filter(|parent_url| !parent_url.is_empty())
+        .ok_or_else(|| {
+            Error::new(format!(
+                "can not resolve {} without the url of the solution declaring it",
+                url
+            ))
+        })?;
+    let resolved = resolve_relative_url(&parent_url, &url)?;
+    log::debug!("resolved {} against {} to {}", url, parent_url, resolved);
+
+    let mut comp = arena.get_mut(id).unwrap();
+    if let Some(git) = comp.impl_.as_any_mut().downcast_mut::<GitDependency>() {
+        git.url = resolved;
+    }
+    Ok(())
+}
+
 pub async fn walk_components<V>(
     visitor: &V,
+    parent_id: ComponentID,
     root_dir: &PathBuf,
     deps_file: &PathBuf,
 ) -> Result<Vec<usize>, Error>
@@ -218,12 +253,17 @@ where
         let d = root_dir.clone();
         let func = async move { visit_component(comp_id, visitor, &d).await };
         futures.push(func);
-        let mut comp = arena.get_mut(comp_id).unwrap();
-        comp.target_dir = root_dir.join(comp.target_dir.clone());
-
-        for child_id in comp.children.iter() {
-            queue.push_back(*child_id);
+        {
+            let mut comp = arena.get_mut(comp_id).unwrap();
+            comp.target_dir = root_dir.join(comp.target_dir.clone());
+            // Linked before it is visited, which might need the url of its parent
+            comp.parent_id = Some(parent_id);
+
+            for child_id in comp.children.iter() {
+                queue.push_back(*child_id);
+            }
         }
+        resolve_url(comp_id)?;
     }
 
     match try_join_all(futures).await {
@@ -255,6 +295,7 @@ where
             commit,
             deps_file,
             paths: None,
+            ..Default::default()
         }),
     };
 
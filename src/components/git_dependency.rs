@@ -4,7 +4,8 @@ use crate::components::component::{ComponentImpl, FromPyObject};
 
 use crate::errors::Error;
 use crane_derive::FromPyObject;
-use rustpython_vm::{PyObjectRef, VirtualMachine};
+use rustpython_vm::builtins::PyDict;
+use rustpython_vm::{PyObjectRef, PyResult, TryFromObject, VirtualMachine};
 
 #[derive(Debug, FromPyObject)]
 pub struct GitDependency {
@@ -18,6 +19,12 @@ pub struct GitDependency {
     pub branch: Option<String>,
     #[from_py]
     pub deps_file: Option<String>,
+    // Name of the remote the pinned revision is fetched from, `url` is its url
+    #[from_py]
+    pub remote: Option<String>,
+    // More remotes set up in the workspace repository, e.g. a fork to push to
+    #[from_py]
+    pub remotes: Option<Remotes>,
 }
 
 impl Default for GitDependency {
@@ -28,6 +35,8 @@ impl Default for GitDependency {
             commit: None,
             branch: None,
             deps_file: None,
+            remote: None,
+            remotes: None,
         }
     }
 }
@@ -41,3 +50,39 @@ impl ComponentImpl for GitDependency {
         self
     }
 }
+
+// The urls of a remote, either a dict or the fetch url alone
+#[derive(Debug, Default, Clone, PartialEq, FromPyObject)]
+pub struct RemoteSpec {
+    #[from_py]
+    pub url: Option<String>,
+    #[from_py]
+    pub push_url: Option<String>,
+}
+
+// Remotes by name in the order they are declared
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Remotes(pub Vec<(String, RemoteSpec)>);
+
+impl TryFromObject for Remotes {
+    fn try_from_object(vm: &VirtualMachine, obj: PyObjectRef) -> PyResult<Self> {
+        let dict = obj
+            .downcast::<PyDict>()
+            .map_err(|_| vm.new_type_error(String::from("remotes should be a dict")))?;
+        let mut remotes = vec![];
+        for (key, value) in dict {
+            let name: String = key.try_into_value(vm)?;
+            let spec = match value.clone().try_into_value::<String>(vm) {
+                Ok(url) => RemoteSpec {
+                    url: Some(url),
+                    push_url: None,
+                },
+                Err(_) => {
+                    RemoteSpec::from_py(&value, vm).map_err(|e| vm.new_type_error(e.message))?
+                }
+            };
+            remotes.push((name, spec));
+        }
+        Ok(Remotes(remotes))
+    }
+}
@@ -4,6 +4,16 @@ use std::env;
 pub const CRANE_FILE: &str = ".crane";
 pub const DEFAULT_LOG_LEVEL: &str = "debug";
 pub const CACHE_DIR: &str = ".crane_cache";
+// Directory in the root of a solution where crane keeps the state of the workspace
+pub const WORKSPACE_DIR: &str = ".crane_workspace";
+// Settings of a workspace in WORKSPACE_DIR, in the format of USER_CONFIG_FILE
+pub const WORKSPACE_CONFIG_FILE: &str = "config";
+pub const XDG_CACHE_DIR: &str = "crane";
+pub const CRANE_CACHE_DIR_ENV: &str = "CRANE_CACHE_DIR";
+pub const USER_CONFIG_FILE: &str = ".cranerc";
+pub const CRANE_CONFIG_ENV: &str = "CRANE_CONFIG";
+// Refs in cache repositories keeping the pinned commits borrowed by workspaces
+pub const CACHE_PINS_REF_PREFIX: &str = "refs/crane/pins/";
 
 lazy_static! {
     pub static ref CRANE_DEBUG: bool = match env::var("CRANE_DEBUG") {
@@ -58,3 +58,11 @@ impl From<anyhow::Error> for Error {
         }
     }
 }
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error {
+            message: format!("{}", value),
+        }
+    }
+}
@@ -28,7 +28,7 @@ async fn main() {
     let cli = Cli::parse();
     match &cli.command {
         Some(cmd) => {
-            let result = run_command(cmd).await;
+            let result = run_command(&cli.global, cmd).await;
             match &result {
                 Ok(_) => {}
                 Err(err) => {
@@ -0,0 +1,233 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::utils::git_utils::get_git_dir_path;
+
+// The object directories a repository borrows objects from are listed in
+// .git/objects/info/alternates, one path per line. Cache repositories live in
+// <cache dir>/git/<cache key>, so their entries end with "<cache key>/.git/objects".
+
+pub fn alternates_file(repo_path: &Path) -> io::Result<PathBuf> {
+    Ok(get_git_dir_path(repo_path)?.join("objects/info/alternates"))
+}
+
+// Read the entries as they are in the file, duplicates included
+pub fn read(repo_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let file = alternates_file(repo_path)?;
+    if !file.exists() {
+        return Ok(vec![]);
+    }
+    Ok(fs::read_to_string(file)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect())
+}
+
+// Rewrite the file with the entries deduplicated, the file is removed if there are none left
+pub fn write(repo_path: &Path, entries: &[PathBuf]) -> io::Result<()> {
+    let file = alternates_file(repo_path)?;
+    let entries = dedup(entries);
+    if entries.is_empty() {
+        if file.exists() {
+            fs::remove_file(file)?;
+        }
+        return Ok(());
+    }
+
+    let content: String = entries
+        .iter()
+        .map(|entry| format!("{}\n", entry.display()))
+        .collect();
+    fs::create_dir_all(file.parent().unwrap())?;
+    // Git might read the file at any time, so it is replaced at once
+    let temp_file = file.with_extension("tmp");
+    fs::write(&temp_file, content)?;
+    fs::rename(temp_file, file)
+}
+
+// Borrow objects from the repository in `alternate_git_dir`. A missing entry of the same cache
+// repository at another location, e.g. before the cache dir moved, is replaced. One which still
+// exists is kept, objects might only be there.
+// Returns false if the entry was already there.
+pub fn add(repo_path: &Path, alternate_git_dir: &Path) -> io::Result<bool> {
+    log::debug!(
+        "add alternate: {} -> {}",
+        repo_path.display(),
+        alternate_git_dir.display()
+    );
+    let objects_dir = alternate_git_dir.join("objects");
+    let current = read(repo_path)?;
+    let key = cache_repo_key(&objects_dir);
+
+    let mut entries: Vec<PathBuf> = current
+        .iter()
+        .filter(|entry| **entry != objects_dir)
+        .filter(|entry| key.is_none() || cache_repo_key(entry) != key || entry.is_dir())
+        .cloned()
+        .collect();
+    entries.push(objects_dir);
+    if entries == current {
+        return Ok(false);
+    }
+    write(repo_path, &entries)?;
+    Ok(true)
+}
+
+// Entries pointing at directories which do not exist anymore, e.g. a removed cache repository
+pub fn missing(repo_path: &Path) -> io::Result<Vec<PathBuf>> {
+    Ok(dedup(&read(repo_path)?)
+        .into_iter()
+        .filter(|entry| !entry.is_dir())
+        .collect())
+}
+
+// Re-point the missing entries of cache repositories to the same repositories in
+// `cache_git_dir`, returns the re-pointed entries as (old, new)
+pub fn repoint(repo_path: &Path, cache_git_dir: &Path) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut repointed = vec![];
+    let entries: Vec<PathBuf> = read(repo_path)?
+        .into_iter()
+        .map(|entry| {
+            if entry.is_dir() {
+                return entry;
+            }
+            let candidate = match cache_repo_key(&entry) {
+                Some(key) => cache_git_dir.join(key).join(".git").join("objects"),
+                None => return entry,
+            };
+            if candidate.is_dir() && candidate != entry {
+                repointed.push((entry, candidate.clone()));
+                candidate
+            } else {
+                entry
+            }
+        })
+        .collect();
+    if !repointed.is_empty() {
+        write(repo_path, &entries)?;
+    }
+    Ok(repointed)
+}
+
+fn dedup(entries: &[PathBuf]) -> Vec<PathBuf> {
+    let mut unique: Vec<PathBuf> = vec![];
+    for entry in entries {
+        if !unique.contains(entry) {
+            unique.push(entry.clone());
+        }
+    }
+    unique
+}
+
+// Name of the repository owning an objects directory like "<cache key>/.git/objects"
+fn cache_repo_key(objects_dir: &Path) -> Option<&OsStr> {
+    if objects_dir.file_name()? != "objects" {
+        return None;
+    }
+    let git_dir = objects_dir.parent()?;
+    if git_dir.file_name()? != ".git" {
+        return None;
+    }
+    git_dir.parent()?.file_name()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Repository;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_add_is_idempotent() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        Repository::init(temp_dir.path()).unwrap();
+        let cache_repo = temp_dir.path().join("cache/git/key/.git");
+
+        assert!(add(temp_dir.path(), &cache_repo).unwrap());
+        assert!(!add(temp_dir.path(), &cache_repo).unwrap());
+        assert_eq!(
+            fs::read_to_string(alternates_file(temp_dir.path()).unwrap()).unwrap(),
+            format!("{}\n", cache_repo.join("objects").display())
+        );
+    }
+
+    #[test]
+    fn test_add_replaces_moved_cache_repo() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        Repository::init(temp_dir.path()).unwrap();
+        let other = PathBuf::from("/srv/objects");
+        let old_cache_repo = temp_dir.path().join("old_cache/git/key/.git");
+        let new_cache_repo = temp_dir.path().join("new_cache/git/key/.git");
+        write(
+            temp_dir.path(),
+            &[
+                old_cache_repo.join("objects"),
+                other.clone(),
+                old_cache_repo.join("objects"),
+            ],
+        )
+        .unwrap();
+
+        assert!(add(temp_dir.path(), &new_cache_repo).unwrap());
+        assert_eq!(
+            read(temp_dir.path()).unwrap(),
+            vec![other, new_cache_repo.join("objects")]
+        );
+    }
+
+    #[test]
+    fn test_add_keeps_existing_cache_repo() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        Repository::init(temp_dir.path()).unwrap();
+        let old_cache_repo = temp_dir.path().join("old_cache/git/key/.git");
+        let new_cache_repo = temp_dir.path().join("new_cache/git/key/.git");
+        fs::create_dir_all(old_cache_repo.join("objects")).unwrap();
+        fs::create_dir_all(new_cache_repo.join("objects")).unwrap();
+
+        assert!(add(temp_dir.path(), &old_cache_repo).unwrap());
+        assert!(add(temp_dir.path(), &new_cache_repo).unwrap());
+        assert_eq!(
+            read(temp_dir.path()).unwrap(),
+            vec![
+                old_cache_repo.join("objects"),
+                new_cache_repo.join("objects")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_and_repoint() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        Repository::init(temp_dir.path()).unwrap();
+        let old_objects = temp_dir.path().join("old_cache/git/key/.git/objects");
+        let new_git_dir = temp_dir.path().join("new_cache/git");
+        write(temp_dir.path(), std::slice::from_ref(&old_objects)).unwrap();
+        assert_eq!(missing(temp_dir.path()).unwrap(), vec![old_objects.clone()]);
+
+        // Nothing to re-point to until the cache repository exists in the new location
+        assert!(repoint(temp_dir.path(), &new_git_dir).unwrap().is_empty());
+
+        let new_objects = new_git_dir.join("key/.git/objects");
+        fs::create_dir_all(&new_objects).unwrap();
+        assert_eq!(
+            repoint(temp_dir.path(), &new_git_dir).unwrap(),
+            vec![(old_objects, new_objects.clone())]
+        );
+        assert_eq!(read(temp_dir.path()).unwrap(), vec![new_objects]);
+        assert!(missing(temp_dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_write_removes_empty_file() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        Repository::init(temp_dir.path()).unwrap();
+        write(temp_dir.path(), &[PathBuf::from("/srv/objects")]).unwrap();
+        assert!(alternates_file(temp_dir.path()).unwrap().exists());
+        write(temp_dir.path(), &[]).unwrap();
+        assert!(!alternates_file(temp_dir.path()).unwrap().exists());
+    }
+}
@@ -1,23 +1,232 @@
-use crate::constants::CACHE_DIR;
+use crate::constants::{CACHE_DIR, CRANE_CACHE_DIR_ENV, XDG_CACHE_DIR};
+use crate::errors::Error;
+use crate::utils::alternates;
+use crate::utils::config::{expand_home, UserConfig};
+use crate::utils::encode::base64_to_string;
+use crate::utils::git_url::GitUrl;
+use crate::utils::git_utils::get_git_dir_path;
+use crate::utils::lock::FileLock;
+use git2::Repository;
 use lazy_static::lazy_static;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use walkdir::WalkDir;
+
+const DEFAULT_CACHE_LOCK_TIMEOUT: u64 = 600;
+// Workspace repositories borrowing objects from the cache repositories, one path per line
+const WORKSPACES_FILE: &str = "workspaces";
+// Set in the config of the cache repositories created since workspaces are registered, older
+// ones might be used by workspaces missing from the registry
+const REGISTERED_CONFIG_KEY: &str = "crane.registersworkspaces";
 
 lazy_static! {
     static ref CACHE_DIR_LOCK: Mutex<usize> = Mutex::new(0);
+    static ref CACHE_DIR_OVERRIDE: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+// Set the cache directory given on the command line, it takes precedence over other settings
+pub fn set_cache_dir_override(cache_dir: Option<PathBuf>) {
+    *CACHE_DIR_OVERRIDE.lock().unwrap() = cache_dir;
+}
+
+// Resolve the cache directory, in order of precedence:
+//   --cache-dir, $CRANE_CACHE_DIR, cache_dir in ~/.cranerc, $XDG_CACHE_HOME/crane, ~/.crane_cache
+pub fn get_cache_dir() -> Result<PathBuf, Error> {
+    let non_empty = |name: &str| env::var_os(name).filter(|v| !v.is_empty());
+    let cache_dir = if let Some(dir) = CACHE_DIR_OVERRIDE.lock().unwrap().clone() {
+        dir
+    } else if let Some(dir) = non_empty(CRANE_CACHE_DIR_ENV) {
+        PathBuf::from(dir)
+    } else if let Some(dir) = &UserConfig::instance().cache_dir {
+        expand_home(dir)
+    } else if let Some(dir) = non_empty("XDG_CACHE_HOME") {
+        PathBuf::from(dir).join(XDG_CACHE_DIR)
+    } else if let Some(home) = home::home_dir() {
+        home.join(CACHE_DIR)
+    } else {
+        return Err(Error::new(format!(
+            "Can not find a cache directory, set {} or pass --cache-dir",
+            CRANE_CACHE_DIR_ENV
+        )));
+    };
+
+    if cache_dir.is_absolute() {
+        Ok(cache_dir)
+    } else {
+        Ok(env::current_dir()?.join(cache_dir))
+    }
 }
 
-pub fn ensure_cache_dir() -> PathBuf {
+pub fn ensure_cache_dir() -> Result<PathBuf, Error> {
     let _guard = CACHE_DIR_LOCK.lock();
-    let cache_dir = PathBuf::from(env::var("HOME").unwrap()).join(CACHE_DIR);
+    let cache_dir = get_cache_dir()?;
     if !cache_dir.exists() {
-        fs::create_dir_all(&cache_dir).unwrap();
+        fs::create_dir_all(&cache_dir)?;
     } else if !cache_dir.is_dir() {
-        panic!("Path {:?} exists but is not a directory", cache_dir);
+        return Err(Error::new(format!(
+            "Path {:?} exists but is not a directory",
+            cache_dir
+        )));
+    }
+    Ok(cache_dir)
+}
+
+pub fn ensure_git_cache_dir() -> Result<PathBuf, Error> {
+    Ok(ensure_cache_dir()?.join("git"))
+}
+
+// Where the cache repository of `url` is, it might not exist yet
+pub fn cache_repo_dir(url: &str) -> Result<PathBuf, Error> {
+    Ok(get_cache_dir()?
+        .join("git")
+        .join(GitUrl::parse(url)?.cache_key()))
+}
+
+#[derive(Debug)]
+pub struct CacheRepo {
+    pub path: PathBuf,
+    pub url: Option<String>,
+    pub size: u64,
+    pub last_fetch: Option<SystemTime>,
+}
+
+// List the repositories in the git cache, the name of each one is its url encoded in base64
+pub fn list_cache_repos() -> Result<Vec<CacheRepo>, Error> {
+    let git_cache_dir = get_cache_dir()?.join("git");
+    if !git_cache_dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut repos = vec![];
+    for entry in fs::read_dir(&git_cache_dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let url = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| base64_to_string(name).ok());
+        let size = WalkDir::new(&path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.metadata().ok())
+            .filter(|m| m.is_file())
+            .map(|m| m.len())
+            .sum();
+        let last_fetch = fs::metadata(path.join(".git/FETCH_HEAD"))
+            .or_else(|_| fs::metadata(&path))
+            .and_then(|m| m.modified())
+            .ok();
+        repos.push(CacheRepo {
+            path,
+            url,
+            size,
+            last_fetch,
+        });
     }
-    cache_dir
+    repos.sort_by(|a, b| a.url.cmp(&b.url));
+    Ok(repos)
+}
+
+// Mark a new cache repository as one whose workspace repositories are all registered
+pub fn mark_registered(cache_repo: &Repository) -> Result<(), Error> {
+    cache_repo.config()?.set_bool(REGISTERED_CONFIG_KEY, true)?;
+    Ok(())
+}
+
+// Whether every workspace repository using the cache repository is in the registry
+pub fn is_registered(cache_repo: &Path) -> bool {
+    Repository::open(cache_repo)
+        .and_then(|repo| repo.config()?.get_bool(REGISTERED_CONFIG_KEY))
+        .unwrap_or(false)
+}
+
+// Record a workspace repository borrowing objects from the cache
+pub fn register_workspace_repo(repo_dir: &Path) -> Result<(), Error> {
+    let repo_dir = repo_dir.canonicalize()?;
+    let cache_dir = ensure_cache_dir()?;
+    let _lock = FileLock::acquire(
+        &cache_dir.join(WORKSPACES_FILE).with_extension("lock"),
+        "workspace registry lock",
+        Duration::from_secs(DEFAULT_CACHE_LOCK_TIMEOUT),
+    )?;
+    let mut repos = registered_workspace_repos()?;
+    if repos.contains(&repo_dir) {
+        return Ok(());
+    }
+    repos.push(repo_dir);
+    write_workspace_repos(&cache_dir, &repos)
+}
+
+pub fn registered_workspace_repos() -> Result<Vec<PathBuf>, Error> {
+    let workspaces_file = get_cache_dir()?.join(WORKSPACES_FILE);
+    if !workspaces_file.exists() {
+        return Ok(vec![]);
+    }
+    Ok(fs::read_to_string(workspaces_file)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+// Drop the workspace repositories which do not exist anymore from the registry
+pub fn forget_missing_workspace_repos() -> Result<(), Error> {
+    let cache_dir = ensure_cache_dir()?;
+    let _lock = FileLock::acquire(
+        &cache_dir.join(WORKSPACES_FILE).with_extension("lock"),
+        "workspace registry lock",
+        Duration::from_secs(DEFAULT_CACHE_LOCK_TIMEOUT),
+    )?;
+    let repos: Vec<PathBuf> = registered_workspace_repos()?
+        .into_iter()
+        .filter(|repo| get_git_dir_path(repo).is_ok())
+        .collect();
+    write_workspace_repos(&cache_dir, &repos)
+}
+
+fn write_workspace_repos(cache_dir: &Path, repos: &[PathBuf]) -> Result<(), Error> {
+    let content: String = repos
+        .iter()
+        .map(|repo| format!("{}\n", repo.display()))
+        .collect();
+    fs::write(cache_dir.join(WORKSPACES_FILE), content)?;
+    Ok(())
+}
+
+// Find the registered workspace repositories borrowing objects from a cache repository, the
+// paths are compared once resolved since the cache dir can be spelled in many ways
+pub fn workspace_repos_using(cache_repo: &Path) -> Result<Vec<PathBuf>, Error> {
+    let objects_dir = cache_repo.join(".git").join("objects").canonicalize()?;
+    Ok(registered_workspace_repos()?
+        .into_iter()
+        .filter(|repo| {
+            alternates::read(repo)
+                .map(|entries| {
+                    entries
+                        .iter()
+                        .filter_map(|entry| entry.canonicalize().ok())
+                        .any(|entry| entry == objects_dir)
+                })
+                .unwrap_or(false)
+        })
+        .collect())
+}
+
+// Lock a cache repository against crane processes syncing other workspaces
+pub fn lock_cache_repo(cache_repo: &Path, url: &str) -> Result<FileLock, Error> {
+    let timeout = UserConfig::instance()
+        .cache_lock_timeout
+        .unwrap_or(DEFAULT_CACHE_LOCK_TIMEOUT);
+    FileLock::acquire(
+        &cache_repo.with_extension("lock"),
+        &format!("cache lock of {}", url),
+        Duration::from_secs(timeout),
+    )
 }
 
 #[cfg(test)]
@@ -30,58 +239,86 @@ mod tests {
         // This lock is used to ensure the test cases running serially because we mock the HOME environment variable in these cases
         static ref TEST_PROCESS_LOCK: Mutex<usize> = Mutex::new(0);
     }
-    #[test]
-    fn test_ensure_cache_dir_creates_directory_if_not_exist() {
-        let _guard = TEST_PROCESS_LOCK.lock();
 
-        let temp_home =
-            TempDir::new("test_cache_dir_1").expect("Failed to create temporary directory");
-        // Save the current value of HOME
-        let original_home = env::var("HOME").ok();
-        // Set HOME to a mock value
-        env::set_var("HOME", temp_home.path().to_str().unwrap());
-
-        let cache_dir = PathBuf::from(env::var("HOME").unwrap()).join(CACHE_DIR);
-        if cache_dir.exists() {
-            let _ = fs::remove_dir_all(&cache_dir).unwrap();
+    // Run a test case with some environment variables set, the others related to cache are unset
+    fn with_env<F: FnOnce()>(vars: &[(&str, &str)], f: F) {
+        let _guard = TEST_PROCESS_LOCK.lock();
+        let names = ["HOME", CRANE_CACHE_DIR_ENV, "XDG_CACHE_HOME"];
+        let saved: Vec<_> = names.iter().map(|n| (n, env::var_os(n))).collect();
+        for name in names {
+            env::remove_var(name);
         }
-        assert!(!cache_dir.exists());
-        ensure_cache_dir();
-        assert!(cache_dir.exists());
-        assert!(cache_dir.is_dir());
-
-        // Restore the original value of HOME
-        if let Some(val) = original_home {
-            env::set_var("HOME", val);
-        } else {
-            env::remove_var("HOME");
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+
+        f();
+
+        for (name, value) in saved {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
         }
     }
+
     #[test]
-    #[should_panic(expected = "not a directory")]
-    fn test_ensure_cache_dir_panics_if_path_exists_but_not_dir() {
-        let _guard = TEST_PROCESS_LOCK.lock();
+    fn test_ensure_cache_dir_creates_directory_if_not_exist() {
+        let temp_home =
+            TempDir::new("test_cache_dir_1").expect("Failed to create temporary directory");
+        with_env(&[("HOME", temp_home.path().to_str().unwrap())], || {
+            let cache_dir = PathBuf::from(env::var("HOME").unwrap()).join(CACHE_DIR);
+            if cache_dir.exists() {
+                let _ = fs::remove_dir_all(&cache_dir).unwrap();
+            }
+            assert!(!cache_dir.exists());
+            ensure_cache_dir().unwrap();
+            assert!(cache_dir.exists());
+            assert!(cache_dir.is_dir());
+        });
+    }
 
+    #[test]
+    fn test_ensure_cache_dir_fails_if_path_exists_but_not_dir() {
         let temp_home =
             TempDir::new("test_cache_dir_2").expect("Failed to create temporary directory");
-        // Save the current value of HOME
-        let original_home = env::var("HOME").ok();
-        // Set HOME to a mock value
-        env::set_var("HOME", temp_home.path().to_str().unwrap());
-
-        let cache_dir = PathBuf::from(env::var("HOME").unwrap()).join(CACHE_DIR);
-        if cache_dir.exists() {
-            let _ = fs::remove_dir_all(&cache_dir).unwrap();
-        }
-        print!("cache_dir: {:?}", cache_dir);
-        fs::File::create(cache_dir).unwrap();
-        ensure_cache_dir();
-
-        // Restore the original value of HOME
-        if let Some(val) = original_home {
-            env::set_var("HOME", val);
-        } else {
-            env::remove_var("HOME");
-        }
+        with_env(&[("HOME", temp_home.path().to_str().unwrap())], || {
+            let cache_dir = PathBuf::from(env::var("HOME").unwrap()).join(CACHE_DIR);
+            if cache_dir.exists() {
+                let _ = fs::remove_dir_all(&cache_dir).unwrap();
+            }
+            fs::File::create(cache_dir).unwrap();
+            let err = ensure_cache_dir().unwrap_err();
+            assert!(err.message.contains("not a directory"));
+        });
+    }
+
+    #[test]
+    fn test_get_cache_dir_precedence() {
+        let temp_dir =
+            TempDir::new("test_cache_dir_3").expect("Failed to create temporary directory");
+        let home = temp_dir.path().join("home");
+        let xdg = temp_dir.path().join("xdg");
+        let crane = temp_dir.path().join("crane");
+        let home_var = ("HOME", home.to_str().unwrap());
+        let xdg_var = ("XDG_CACHE_HOME", xdg.to_str().unwrap());
+        let crane_var = (CRANE_CACHE_DIR_ENV, crane.to_str().unwrap());
+
+        with_env(&[home_var], || {
+            assert_eq!(get_cache_dir().unwrap(), home.join(CACHE_DIR));
+        });
+        with_env(&[home_var, xdg_var], || {
+            assert_eq!(get_cache_dir().unwrap(), xdg.join(XDG_CACHE_DIR));
+        });
+        with_env(&[home_var, xdg_var, crane_var], || {
+            assert_eq!(get_cache_dir().unwrap(), crane);
+        });
+        with_env(&[xdg_var, crane_var], || {
+            let overridden = temp_dir.path().join("override");
+            set_cache_dir_override(Some(overridden.clone()));
+            assert_eq!(get_cache_dir().unwrap(), overridden);
+            set_cache_dir_override(None);
+            assert_eq!(get_cache_dir().unwrap(), crane);
+        });
     }
 }
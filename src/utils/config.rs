@@ -0,0 +1,223 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crane_derive::FromPyObject;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use rustpython_vm::{PyObjectRef, VirtualMachine};
+
+use crate::components::component::FromPyObject;
+use crate::constants::{CRANE_CONFIG_ENV, USER_CONFIG_FILE, WORKSPACE_CONFIG_FILE, WORKSPACE_DIR};
+use crate::errors::Error;
+use crate::utils::parser;
+use crate::utils::rustpython::Dict;
+
+lazy_static! {
+    // The settings in effect, those of the user merged with those of the workspace if any
+    static ref INSTANCE: RwLock<Arc<UserConfig>> = RwLock::new(Arc::new(UserConfig::default()));
+    // The settings of the user alone, which every workspace is merged with
+    static ref USER_INSTANCE: RwLock<Arc<UserConfig>> =
+        RwLock::new(Arc::new(UserConfig::default()));
+}
+
+// Settings of the current user, loaded from the python format file ~/.cranerc
+#[derive(Debug, Default, FromPyObject)]
+pub struct UserConfig {
+    // Order of the credential providers tried when a remote asks for authentication
+    #[from_py]
+    pub credential_providers: Option<Vec<String>>,
+    // Private keys tried by the "ssh-key" credential provider
+    #[from_py]
+    pub ssh_keys: Option<Vec<String>>,
+    // Directory of the global cache, overridden by $CRANE_CACHE_DIR and --cache-dir
+    #[from_py]
+    pub cache_dir: Option<String>,
+    // Seconds to wait for another crane process fetching into the same cache repository
+    #[from_py]
+    pub cache_lock_timeout: Option<u64>,
+    // Prefixes of remote urls and what they are replaced with, like url.<base>.insteadOf of git
+    #[from_py]
+    pub url_rewrites: Option<Dict<String>>,
+    // Mirrors of each host tried in order before the host itself
+    #[from_py]
+    pub mirrors: Option<Dict<Vec<String>>>,
+    // Times a failed fetch is retried, and seconds to wait before the first retry
+    #[from_py]
+    pub fetch_retries: Option<u32>,
+    #[from_py]
+    pub fetch_retry_delay: Option<u64>,
+    // Seconds to wait for a remote to respond, and for more data once it did
+    #[from_py]
+    pub connect_timeout: Option<u64>,
+    #[from_py]
+    pub stall_timeout: Option<u64>,
+}
+
+impl UserConfig {
+    pub fn instance() -> Arc<UserConfig> {
+        INSTANCE.read().clone()
+    }
+
+    // Set the settings of the user, they are in effect until a workspace is applied
+    pub fn set_instance(config: UserConfig) {
+        let config = Arc::new(config);
+        *USER_INSTANCE.write() = config.clone();
+        *INSTANCE.write() = config;
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        match env::var(CRANE_CONFIG_ENV) {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => home::home_dir().map(|home| home.join(USER_CONFIG_FILE)),
+        }
+    }
+
+    pub fn load() -> Result<UserConfig, Error> {
+        match UserConfig::path() {
+            Some(path) if path.exists() => parser::parse_globals::<UserConfig>(&path),
+            _ => Ok(UserConfig::default()),
+        }
+    }
+
+    // Settings of the workspace in `root_dir` take precedence over those of the user, the
+    // rewrites and mirrors of both are kept. They replace those of a workspace applied before.
+    pub fn apply_workspace(root_dir: &Path) -> Result<(), Error> {
+        let user = USER_INSTANCE.read().clone();
+        let path = root_dir.join(WORKSPACE_DIR).join(WORKSPACE_CONFIG_FILE);
+        let config = match path.exists() {
+            true => Arc::new(parser::parse_globals::<UserConfig>(&path)?.merge(&user)),
+            false => user,
+        };
+        *INSTANCE.write() = config;
+        Ok(())
+    }
+
+    fn merge(self, other: &UserConfig) -> UserConfig {
+        fn chain<T: Clone>(first: Option<Dict<T>>, second: &Option<Dict<T>>) -> Option<Dict<T>> {
+            match (first, second) {
+                (Some(mut first), Some(second)) => {
+                    first.0.extend(second.0.iter().cloned());
+                    Some(first)
+                }
+                (first, second) => first.or_else(|| second.clone()),
+            }
+        }
+        UserConfig {
+            credential_providers: self
+                .credential_providers
+                .or_else(|| other.credential_providers.clone()),
+            ssh_keys: self.ssh_keys.or_else(|| other.ssh_keys.clone()),
+            cache_dir: self.cache_dir.or_else(|| other.cache_dir.clone()),
+            cache_lock_timeout: self.cache_lock_timeout.or(other.cache_lock_timeout),
+            url_rewrites: chain(self.url_rewrites, &other.url_rewrites),
+            mirrors: chain(self.mirrors, &other.mirrors),
+            fetch_retries: self.fetch_retries.or(other.fetch_retries),
+            fetch_retry_delay: self.fetch_retry_delay.or(other.fetch_retry_delay),
+            connect_timeout: self.connect_timeout.or(other.connect_timeout),
+            stall_timeout: self.stall_timeout.or(other.stall_timeout),
+        }
+    }
+}
+
+// Expand a leading "~" to the home directory of current user
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_parse_user_config() {
+        let temp_dir = TempDir::new("user_config").expect("Failed to create temporary directory");
+        let config_file = temp_dir.path().join(USER_CONFIG_FILE);
+        fs::write(
+            &config_file,
+            r#"
+credential_providers = ["ssh-agent", "env"]
+ssh_keys = ["~/.ssh/id_work"]
+"#,
+        )
+        .unwrap();
+
+        let config = parser::parse_globals::<UserConfig>(&config_file).unwrap();
+        assert_eq!(
+            config.credential_providers,
+            Some(vec!["ssh-agent".to_string(), "env".to_string()])
+        );
+        assert_eq!(config.ssh_keys, Some(vec!["~/.ssh/id_work".to_string()]));
+    }
+
+    #[test]
+    fn test_merge_workspace_config() {
+        let temp_dir = TempDir::new("user_config").expect("Failed to create temporary directory");
+        let config_file = temp_dir.path().join(USER_CONFIG_FILE);
+        fs::write(
+            &config_file,
+            r#"
+ssh_keys = ["~/.ssh/id_work"]
+url_rewrites = {"https://github.com/": "https://mirror.example.com/github/"}
+mirrors = {"github.com": ["https://eu.example.com/github", "https://cn.example.com/github"]}
+"#,
+        )
+        .unwrap();
+        let user = parser::parse_globals::<UserConfig>(&config_file).unwrap();
+        fs::write(
+            &config_file,
+            r#"
+ssh_keys = ["~/.ssh/id_workspace"]
+url_rewrites = {"https://github.com/org/": "https://internal.example.com/org/"}
+"#,
+        )
+        .unwrap();
+        let workspace = parser::parse_globals::<UserConfig>(&config_file).unwrap();
+
+        let config = workspace.merge(&user);
+        assert_eq!(
+            config.ssh_keys,
+            Some(vec!["~/.ssh/id_workspace".to_string()])
+        );
+        assert_eq!(config.url_rewrites.unwrap().0.len(), 2);
+        assert_eq!(config.mirrors.unwrap().0[0].1.len(), 2);
+    }
+
+    #[test]
+    fn test_apply_workspace_config() {
+        let workspace_dir =
+            TempDir::new("workspace").expect("Failed to create temporary directory");
+        let config_dir = workspace_dir.path().join(WORKSPACE_DIR);
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(
+            config_dir.join(WORKSPACE_CONFIG_FILE),
+            r#"mirrors = {"example.invalid": ["https://workspace.example.invalid"]}"#,
+        )
+        .unwrap();
+        let other_dir = TempDir::new("workspace").expect("Failed to create temporary directory");
+        UserConfig::set_instance(UserConfig {
+            mirrors: Some(Dict(vec![(
+                "example.invalid".to_string(),
+                vec!["https://user.example.invalid".to_string()],
+            )])),
+            ..UserConfig::default()
+        });
+
+        // Applying it again does not repeat the entries
+        UserConfig::apply_workspace(workspace_dir.path()).unwrap();
+        UserConfig::apply_workspace(workspace_dir.path()).unwrap();
+        let mirrors = UserConfig::instance().mirrors.clone().unwrap();
+        assert_eq!(mirrors.0.len(), 2);
+
+        // Nor does it leak into another workspace
+        UserConfig::apply_workspace(other_dir.path()).unwrap();
+        let mirrors = UserConfig::instance().mirrors.clone().unwrap();
+        assert_eq!(mirrors.0.len(), 1);
+        UserConfig::set_instance(UserConfig::default());
+    }
+}
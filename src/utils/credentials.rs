@@ -0,0 +1,344 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use git2::{Cred, CredentialType};
+use url::Url;
+
+use crate::errors::Error;
+use crate::utils::config::{expand_home, UserConfig};
+
+pub const GIT_TOKEN_ENV: &str = "CRANE_GIT_TOKEN";
+pub const GIT_USERNAME_ENV: &str = "CRANE_GIT_USERNAME";
+
+const DEFAULT_SSH_KEYS: &[&str] = &["~/.ssh/id_ed25519", "~/.ssh/id_ecdsa", "~/.ssh/id_rsa"];
+
+// A remote rejecting every credential keeps asking for new ones, stop offering after this many
+const MAX_CREDENTIAL_ATTEMPTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialProvider {
+    SshAgent,
+    SshKey,
+    CredentialHelper,
+    Netrc,
+    EnvToken,
+}
+
+pub const DEFAULT_CREDENTIAL_PROVIDERS: &[CredentialProvider] = &[
+    CredentialProvider::SshAgent,
+    CredentialProvider::SshKey,
+    CredentialProvider::CredentialHelper,
+    CredentialProvider::Netrc,
+    CredentialProvider::EnvToken,
+];
+
+impl FromStr for CredentialProvider {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ssh-agent" => Ok(CredentialProvider::SshAgent),
+            "ssh-key" => Ok(CredentialProvider::SshKey),
+            "credential-helper" => Ok(CredentialProvider::CredentialHelper),
+            "netrc" => Ok(CredentialProvider::Netrc),
+            "env" => Ok(CredentialProvider::EnvToken),
+            _ => Err(Error::new(format!(
+                "unknown credential provider \"{}\", expect one of ssh-agent, ssh-key, credential-helper, netrc, env",
+                s
+            ))),
+        }
+    }
+}
+
+// Offers the credentials of each provider in order, every credential is offered at most once
+pub struct CredentialChain {
+    providers: Vec<CredentialProvider>,
+    ssh_keys: Vec<PathBuf>,
+    provider_index: usize,
+    key_index: usize,
+    attempts: usize,
+}
+
+impl CredentialChain {
+    pub fn new(providers: Vec<CredentialProvider>, ssh_keys: Vec<PathBuf>) -> Self {
+        CredentialChain {
+            providers,
+            ssh_keys,
+            provider_index: 0,
+            key_index: 0,
+            attempts: 0,
+        }
+    }
+
+    pub fn from_config(config: &UserConfig) -> Result<Self, Error> {
+        let providers = match &config.credential_providers {
+            Some(names) => names
+                .iter()
+                .map(|name| CredentialProvider::from_str(name))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => DEFAULT_CREDENTIAL_PROVIDERS.to_vec(),
+        };
+        let ssh_keys = match &config.ssh_keys {
+            Some(keys) => keys.iter().map(|k| expand_home(k)).collect(),
+            None => DEFAULT_SSH_KEYS.iter().map(|k| expand_home(k)).collect(),
+        };
+        Ok(CredentialChain::new(providers, ssh_keys))
+    }
+
+    pub fn next_credential(
+        &mut self,
+        url: &str,
+        username_from_url: Option<&str>,
+        allowed_types: CredentialType,
+    ) -> Result<Cred, git2::Error> {
+        self.attempts += 1;
+        if self.attempts > MAX_CREDENTIAL_ATTEMPTS {
+            return Err(git2::Error::from_str(&format!(
+                "authentication failed for {} after {} attempts",
+                url, MAX_CREDENTIAL_ATTEMPTS
+            )));
+        }
+
+        let username = username_from_url
+            .map(|u| u.to_string())
+            .or_else(|| env::var("USER").ok())
+            .unwrap_or_else(|| String::from("git"));
+
+        // SSH transports ask for the username first if it is not in the url
+        if allowed_types.contains(CredentialType::USERNAME) {
+            return Cred::username(&username);
+        }
+
+        while let Some(provider) = self.providers.get(self.provider_index).copied() {
+            if let Some(cred) = self.next_from_provider(provider, url, &username, allowed_types) {
+                log::debug!("try credential from provider {:?} for {}", provider, url);
+                return Ok(cred);
+            }
+            self.provider_index += 1;
+            self.key_index = 0;
+        }
+
+        Err(git2::Error::from_str(&format!(
+            "authentication failed for {}: no more credentials to try",
+            url
+        )))
+    }
+
+    fn next_from_provider(
+        &mut self,
+        provider: CredentialProvider,
+        url: &str,
+        username: &str,
+        allowed_types: CredentialType,
+    ) -> Option<Cred> {
+        let ssh_key = allowed_types.contains(CredentialType::SSH_KEY);
+        let user_pass = allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT);
+        match provider {
+            CredentialProvider::SshAgent if ssh_key && self.key_index == 0 => {
+                self.key_index += 1;
+                env::var("SSH_AUTH_SOCK").ok()?;
+                Cred::ssh_key_from_agent(username).ok()
+            }
+            CredentialProvider::SshKey if ssh_key => {
+                while let Some(key) = self.ssh_keys.get(self.key_index) {
+                    self.key_index += 1;
+                    if !key.exists() {
+                        continue;
+                    }
+                    let public_key = key.with_extension("pub");
+                    let public_key = public_key.exists().then_some(public_key);
+                    // A key which can not be used is skipped for the next one
+                    match Cred::ssh_key(username, public_key.as_deref(), key, None) {
+                        Ok(cred) => return Some(cred),
+                        Err(e) => log::warn!("skip ssh key {}: {}", key.display(), e),
+                    }
+                }
+                None
+            }
+            CredentialProvider::CredentialHelper if user_pass && self.key_index == 0 => {
+                self.key_index += 1;
+                let (user, password) = git_credential_fill(url)?;
+                Cred::userpass_plaintext(&user, &password).ok()
+            }
+            CredentialProvider::Netrc if user_pass && self.key_index == 0 => {
+                self.key_index += 1;
+                let host = Url::parse(url).ok()?.host_str()?.to_string();
+                let netrc = env::var("NETRC")
+                    .map(PathBuf::from)
+                    .ok()
+                    .or_else(|| home::home_dir().map(|h| h.join(".netrc")))?;
+                let content = fs::read_to_string(netrc).ok()?;
+                let (user, password) = parse_netrc(&content, &host)?;
+                Cred::userpass_plaintext(&user, &password).ok()
+            }
+            CredentialProvider::EnvToken if user_pass && self.key_index == 0 => {
+                self.key_index += 1;
+                let token = env::var(GIT_TOKEN_ENV).ok()?;
+                let user =
+                    env::var(GIT_USERNAME_ENV).unwrap_or_else(|_| String::from("x-access-token"));
+                Cred::userpass_plaintext(&user, &token).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+// Ask the credential helpers configured in git with "git credential fill"
+fn git_credential_fill(url: &str) -> Option<(String, String)> {
+    // The output contains the password, so it is not logged
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child
+        .stdin
+        .take()?
+        .write_all(format!("url={}\n\n", url).as_bytes())
+        .ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let mut username = None;
+    let mut password = None;
+    for line in stdout.lines() {
+        match line.split_once('=') {
+            Some(("username", value)) => username = Some(value.to_string()),
+            Some(("password", value)) => password = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    Some((username?, password?))
+}
+
+// Find the login and password of a host in the content of a .netrc file
+pub fn parse_netrc(content: &str, host: &str) -> Option<(String, String)> {
+    let mut tokens = content.split_whitespace();
+    let mut matched = false;
+    let mut login = None;
+    let mut password = None;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            // Entries after the matched one are ignored, "default" is always the last entry
+            "machine" | "default" if matched => break,
+            "machine" => matched = tokens.next() == Some(host),
+            "default" => matched = true,
+            "login" | "password" | "account" => {
+                let value = tokens.next();
+                if matched && token == "login" {
+                    login = value;
+                } else if matched && token == "password" {
+                    password = value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some((login?.to_string(), password?.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_parse_netrc() {
+        let content = r#"
+machine github.com
+  login alice
+  password secret1
+machine gitlab.com login bob password secret2
+default login anonymous password guest
+"#;
+        assert_eq!(
+            parse_netrc(content, "github.com"),
+            Some(("alice".to_string(), "secret1".to_string()))
+        );
+        assert_eq!(
+            parse_netrc(content, "gitlab.com"),
+            Some(("bob".to_string(), "secret2".to_string()))
+        );
+        assert_eq!(
+            parse_netrc(content, "example.com"),
+            Some(("anonymous".to_string(), "guest".to_string()))
+        );
+        assert_eq!(
+            parse_netrc("machine github.com login alice", "github.com"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_credential_provider_from_str() {
+        assert_eq!(
+            CredentialProvider::from_str("ssh-agent").unwrap(),
+            CredentialProvider::SshAgent
+        );
+        assert_eq!(
+            CredentialProvider::from_str("netrc").unwrap(),
+            CredentialProvider::Netrc
+        );
+        assert!(CredentialProvider::from_str("password").is_err());
+    }
+
+    #[test]
+    fn test_credential_chain_offers_each_key_once() {
+        let temp_dir = TempDir::new("ssh_keys").expect("Failed to create temporary directory");
+        let key1 = temp_dir.path().join("id_ed25519");
+        let key2 = temp_dir.path().join("id_rsa");
+        File::create(&key1).unwrap();
+        File::create(&key2).unwrap();
+
+        let mut chain = CredentialChain::new(
+            vec![CredentialProvider::SshKey],
+            vec![key1, temp_dir.path().join("missing"), key2],
+        );
+        let url = "ssh://git@example.com/repo.git";
+        assert!(chain
+            .next_credential(url, Some("git"), CredentialType::SSH_KEY)
+            .is_ok());
+        assert!(chain
+            .next_credential(url, Some("git"), CredentialType::SSH_KEY)
+            .is_ok());
+        assert!(chain
+            .next_credential(url, Some("git"), CredentialType::SSH_KEY)
+            .is_err());
+    }
+
+    #[test]
+    fn test_credential_chain_limits_attempts() {
+        let temp_dir = TempDir::new("ssh_keys").expect("Failed to create temporary directory");
+        let keys: Vec<PathBuf> = (0..MAX_CREDENTIAL_ATTEMPTS * 2)
+            .map(|i| {
+                let key = temp_dir.path().join(format!("id_{}", i));
+                File::create(&key).unwrap();
+                key
+            })
+            .collect();
+
+        let mut chain = CredentialChain::new(vec![CredentialProvider::SshKey], keys);
+        let url = "ssh://git@example.com/repo.git";
+        for _ in 0..MAX_CREDENTIAL_ATTEMPTS {
+            assert!(chain
+                .next_credential(url, Some("git"), CredentialType::SSH_KEY)
+                .is_ok());
+        }
+        assert!(chain
+            .next_credential(url, Some("git"), CredentialType::SSH_KEY)
+            .is_err());
+    }
+}
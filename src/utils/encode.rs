@@ -1,10 +1,20 @@
 use base64::{engine::general_purpose, Engine as _};
 
+use crate::errors::Error;
+
 pub fn string_to_base64(s: &String) -> String {
     let encoded: String = general_purpose::STANDARD_NO_PAD.encode(s.as_bytes());
     encoded
 }
 
+pub fn base64_to_string(s: &str) -> Result<String, Error> {
+    let decoded = general_purpose::STANDARD_NO_PAD
+        .decode(s)
+        .map_err(|err| Error::new(format!("Invalid base64 string {}: {}", s, err)))?;
+    String::from_utf8(decoded)
+        .map_err(|err| Error::new(format!("Invalid utf-8 string in {}: {}", s, err)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -15,4 +25,13 @@ mod tests {
             string_to_base64(&String::from("this is a test case"))
         );
     }
+
+    #[test]
+    fn test_base64_to_string() {
+        assert_eq!(
+            "this is a test case",
+            base64_to_string("dGhpcyBpcyBhIHRlc3QgY2FzZQ").unwrap()
+        );
+        assert!(base64_to_string("not base64!").is_err());
+    }
 }
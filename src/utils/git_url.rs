@@ -0,0 +1,305 @@
+use std::env;
+use std::fmt;
+
+use url::Url;
+
+use crate::errors::Error;
+use crate::utils::encode::string_to_base64;
+
+// A remote url in any of the forms git understands:
+//   https://host/org/repo.git, ssh://git@host:22/org/repo.git, git://host/org/repo.git,
+//   git@host:org/repo.git (scp-like), file:///srv/git/repo.git and /srv/git/repo.git
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitUrl {
+    pub scheme: String,
+    pub user: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub path: String,
+    raw: String,
+}
+
+impl GitUrl {
+    pub fn parse(url: &str) -> Result<GitUrl, Error> {
+        let invalid = || Error::new(format!("invalid remote url {}", url));
+        let url = url.trim();
+
+        if url.contains("://") {
+            let parsed = Url::parse(url).map_err(|_| invalid())?;
+            let scheme = match parsed.scheme() {
+                "git+ssh" | "ssh+git" => "ssh",
+                s @ ("http" | "https" | "ssh" | "git" | "file") => s,
+                _ => return Err(invalid()),
+            };
+            let user = Some(parsed.username())
+                .filter(|u| !u.is_empty())
+                .map(|u| u.to_string());
+            let host = parsed.host_str().map(|h| h.to_string());
+            if scheme != "file" && host.is_none() {
+                return Err(invalid());
+            }
+            return Ok(GitUrl {
+                scheme: scheme.to_string(),
+                user,
+                host,
+                port: parsed.port(),
+                path: parsed.path().to_string(),
+                raw: url.to_string(),
+            });
+        }
+
+        if let Some((user, host, path)) = split_scp_like(url) {
+            return Ok(GitUrl {
+                scheme: String::from("ssh"),
+                user,
+                host: Some(host),
+                port: None,
+                path: format!("/{}", path.trim_start_matches('/')),
+                raw: url.to_string(),
+            });
+        }
+
+        // Local paths must be explicit to not be confused with arbitrary strings
+        if url.starts_with('/') || url.starts_with("./") || url.starts_with("../") {
+            let path = env::current_dir()?.join(url);
+            return Ok(GitUrl {
+                scheme: String::from("file"),
+                user: None,
+                host: None,
+                port: None,
+                path: path.to_string_lossy().to_string(),
+                raw: url.to_string(),
+            });
+        }
+
+        Err(invalid())
+    }
+
+    // The url passed to git when fetching
+    pub fn fetch_url(&self) -> String {
+        if self.is_local_path() {
+            self.path.clone()
+        } else {
+            self.raw.clone()
+        }
+    }
+
+    // A normalized form of the url, different spellings of the same remote share it
+    pub fn canonical(&self) -> String {
+        let mut canonical = format!("{}://", self.scheme);
+        if self.scheme == "ssh" {
+            if let Some(user) = &self.user {
+                canonical.push_str(&format!("{}@", user));
+            }
+        }
+        if let Some(host) = &self.host {
+            canonical.push_str(&host.to_lowercase());
+        }
+        if let Some(port) = self.port {
+            canonical.push_str(&format!(":{}", port));
+        }
+        let path = self.path.trim_end_matches('/');
+        canonical.push_str(&format!("/{}", path.trim_start_matches('/')));
+        canonical
+    }
+
+    // Key of the repository in the global cache
+    pub fn cache_key(&self) -> String {
+        string_to_base64(&self.canonical())
+    }
+
+    // The scheme used to look up proxy settings, git only goes through proxies over http(s)
+    pub fn proxy_scheme(&self) -> Option<&str> {
+        match self.scheme.as_str() {
+            "http" | "https" => Some(self.scheme.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn repo_name(&self) -> Option<String> {
+        let repo_name = self
+            .path
+            .split('/')
+            .rfind(|s| *s != ".git" && !s.is_empty())?;
+        let repo_name = repo_name.strip_suffix(".git").unwrap_or(repo_name);
+        Some(repo_name.to_string())
+    }
+
+    pub fn is_local_path(&self) -> bool {
+        self.scheme == "file" && !self.raw.contains("://")
+    }
+}
+
+impl fmt::Display for GitUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+// Whether `url` is relative to the url of the repository declaring it, like the url of a git
+// submodule
+pub fn is_relative_url(url: &str) -> bool {
+    url.starts_with("./") || url.starts_with("../")
+}
+
+// Resolve a relative url against `base` the way git resolves the url of a submodule against
+// the url of its superproject, each "../" drops the last component of `base`
+pub fn resolve_relative_url(base: &str, relative: &str) -> Result<String, Error> {
+    let base = base.trim().trim_end_matches('/');
+    let cannot_resolve = || Error::new(format!("can not resolve {} against {}", relative, base));
+    GitUrl::parse(base).map_err(|_| cannot_resolve())?;
+
+    // The scheme and host are never dropped
+    let root_len = match base.find("://") {
+        Some(i) => base[i + 3..].find('/').map_or(base.len(), |j| i + 3 + j),
+        None => match split_scp_like(base) {
+            Some(_) => base.find(':').unwrap() + 1,
+            None => 0,
+        },
+    };
+    let (root, path) = base.split_at(root_len);
+    let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    let mut rest = relative;
+    loop {
+        if let Some(r) = rest.strip_prefix("./") {
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("../") {
+            segments.pop().ok_or_else(cannot_resolve)?;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+    segments.extend(rest.split('/').filter(|s| !s.is_empty()));
+
+    let path = segments.join("/");
+    Ok(match root {
+        "" if base.starts_with('/') => format!("/{}", path),
+        "" => path,
+        root if root.ends_with(':') => format!("{}{}", root, path),
+        root => format!("{}/{}", root, path),
+    })
+}
+
+// Split "[user@]host:path" into its parts. The form "user@host/path" is accepted as well.
+fn split_scp_like(url: &str) -> Option<(Option<String>, String, String)> {
+    let (user, rest) = match url.split_once('@') {
+        Some((user, rest)) if !user.is_empty() && !user.contains('/') => {
+            (Some(user.to_string()), rest)
+        }
+        _ => (None, url),
+    };
+
+    let colon = rest.find(':');
+    let slash = rest.find('/');
+    let (host, path) = match (colon, slash) {
+        (Some(c), Some(s)) if c < s => (&rest[..c], &rest[c + 1..]),
+        (Some(c), None) => (&rest[..c], &rest[c + 1..]),
+        (_, Some(s)) if user.is_some() => (&rest[..s], &rest[s + 1..]),
+        _ => return None,
+    };
+
+    // A single letter before the colon is a Windows drive rather than a host
+    if host.len() < 2 || host.contains(char::is_whitespace) || path.is_empty() {
+        return None;
+    }
+    Some((user, host.to_string(), path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scp_like_url() {
+        let url = GitUrl::parse("git@github.com:org/repo.git").unwrap();
+        assert_eq!(url.scheme, "ssh");
+        assert_eq!(url.user, Some("git".to_string()));
+        assert_eq!(url.host, Some("github.com".to_string()));
+        assert_eq!(url.path, "/org/repo.git");
+        assert_eq!(url.fetch_url(), "git@github.com:org/repo.git");
+        assert_eq!(url.canonical(), "ssh://git@github.com/org/repo.git");
+        assert_eq!(url.proxy_scheme(), None);
+    }
+
+    #[test]
+    fn test_parse_ssh_url() {
+        let url = GitUrl::parse("ssh://git@github.com:2222/org/repo.git").unwrap();
+        assert_eq!(url.scheme, "ssh");
+        assert_eq!(url.port, Some(2222));
+        assert_eq!(url.canonical(), "ssh://git@github.com:2222/org/repo.git");
+
+        assert_eq!(
+            GitUrl::parse("ssh://git@github.com/org/repo.git")
+                .unwrap()
+                .cache_key(),
+            GitUrl::parse("git@github.com:org/repo.git")
+                .unwrap()
+                .cache_key()
+        );
+    }
+
+    #[test]
+    fn test_parse_http_url() {
+        let url = GitUrl::parse("https://GitHub.com/org/repo.git/").unwrap();
+        assert_eq!(url.scheme, "https");
+        assert_eq!(url.canonical(), "https://github.com/org/repo.git");
+        assert_eq!(url.proxy_scheme(), Some("https"));
+        assert_eq!(url.repo_name(), Some("repo".to_string()));
+    }
+
+    #[test]
+    fn test_parse_local_path() {
+        let url = GitUrl::parse("/srv/git/foo.git").unwrap();
+        assert_eq!(url.scheme, "file");
+        assert!(url.is_local_path());
+        assert_eq!(url.fetch_url(), "/srv/git/foo.git");
+        assert_eq!(url.canonical(), "file:///srv/git/foo.git");
+        assert_eq!(url.repo_name(), Some("foo".to_string()));
+        assert_eq!(
+            url.cache_key(),
+            GitUrl::parse("file:///srv/git/foo.git")
+                .unwrap()
+                .cache_key()
+        );
+
+        let url = GitUrl::parse("file:///srv/git/foo/.git").unwrap();
+        assert!(!url.is_local_path());
+        assert_eq!(url.repo_name(), Some("foo".to_string()));
+    }
+
+    #[test]
+    fn test_resolve_relative_url() {
+        assert!(is_relative_url("../lint.git"));
+        assert!(!is_relative_url("/srv/git/lint.git"));
+        assert_eq!(
+            resolve_relative_url("https://host/org/main.git", "../tools/lint.git").unwrap(),
+            "https://host/org/tools/lint.git"
+        );
+        assert_eq!(
+            resolve_relative_url("https://host/org/main.git/", "./sub.git").unwrap(),
+            "https://host/org/main.git/sub.git"
+        );
+        assert_eq!(
+            resolve_relative_url("git@host:org/main.git", "../../lib.git").unwrap(),
+            "git@host:lib.git"
+        );
+        assert_eq!(
+            resolve_relative_url("file:///srv/main/.git", "../../lib/.git").unwrap(),
+            "file:///srv/lib/.git"
+        );
+        assert_eq!(
+            resolve_relative_url("/srv/git/main.git", "../lib.git").unwrap(),
+            "/srv/git/lib.git"
+        );
+        assert!(resolve_relative_url("https://host/main.git", "../../lib.git").is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_url() {
+        assert!(GitUrl::parse("not a url").is_err());
+        assert!(GitUrl::parse("ftp://example.com/repo.git").is_err());
+        assert!(GitUrl::parse("C:\\repo").is_err());
+    }
+}
@@ -1,36 +1,37 @@
-use git2::{AnnotatedCommit, FetchOptions, ObjectType, ProxyOptions, RemoteCallbacks, Repository};
-use std::fs::{read_to_string, OpenOptions};
-use std::io::Write;
+use git2::build::CheckoutBuilder;
+use git2::{
+    AnnotatedCommit, BranchType, Config, Direction, ErrorClass, ErrorCode, FetchOptions,
+    ObjectType, Oid, ProxyOptions, Remote, RemoteCallbacks, RemoteConnection, Repository,
+    Signature, StashFlags, Status, StatusOptions,
+};
+use std::env;
+use std::fs::read_to_string;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
-use std::{env, fs};
-use url::Url;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::constants::CACHE_PINS_REF_PREFIX;
 use crate::errors::Error;
+use crate::utils::alternates;
+use crate::utils::config::UserConfig;
+use crate::utils::credentials::CredentialChain;
+use crate::utils::git_url::GitUrl;
+use crate::utils::mirrors::with_fallback;
+use crate::utils::process::Command;
+use crate::utils::retry::{retry, FetchPolicy};
 
-pub fn get_repo_name(repo_url: &str) -> Option<String> {
-    let repo_url = if repo_url.starts_with("git@") {
-        repo_url.replace(":", "/").replace("git@", "https://")
-    } else {
-        repo_url.to_string()
-    };
-
-    let url = Url::parse(repo_url.as_str()).ok()?;
-    let segments: Vec<&str> = url
-        .path_segments()?
-        .filter(|s| *s != ".git" && *s != "")
-        .collect();
-    let repo_name = segments.last()?;
-
-    let repo_name = if repo_name.ends_with(".git") {
-        &repo_name[..repo_name.len() - 4]
-    } else {
-        repo_name
-    };
+// How often a running fetch is checked for timeouts
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
 
-    Some(repo_name.to_string())
+pub fn get_repo_name(repo_url: &str) -> Option<String> {
+    GitUrl::parse(repo_url).ok()?.repo_name()
 }
 
-fn get_git_dir_path(repo_path: &Path) -> std::io::Result<PathBuf> {
+pub fn get_git_dir_path(repo_path: &Path) -> std::io::Result<PathBuf> {
     if repo_path.join(".git").is_dir() {
         Ok(repo_path.join(".git"))
     } else {
@@ -57,43 +58,64 @@ pub fn open_or_create_repo(path: &Path) -> Result<Repository, Error> {
     }
 }
 
-pub fn add_alternate(repo_path: &Path, alternate_path: &Path) -> std::io::Result<()> {
-    log::debug!(
-        "add alternate: {} -> {}",
-        repo_path.to_str().unwrap(),
-        alternate_path.to_str().unwrap()
-    );
-    let git_dir_path = get_git_dir_path(repo_path)?;
-    let info_dir = git_dir_path.join("objects/info");
+// Copy the objects borrowed from alternates into the repository and drop the alternates,
+// returns false if the repository has no alternates
+pub fn dissociate(repo_path: &Path) -> Result<bool, Error> {
+    if alternates::read(repo_path)?.is_empty() {
+        return Ok(false);
+    }
 
-    if !info_dir.exists() {
-        fs::create_dir_all(&info_dir)?;
+    let missing = alternates::missing(repo_path)?;
+    if !missing.is_empty() {
+        return Err(Error::new(format!(
+            "Can not dissociate {}, the objects in {} are missing",
+            repo_path.display(),
+            missing
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
     }
 
-    let mut alternates_file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .append(true)
-        .open(info_dir.join("alternates"))?;
-    writeln!(
-        alternates_file,
-        "{}",
-        alternate_path.join("objects").display()
-    )
+    // Without --local, repack copies the objects reachable from alternates as well
+    log::debug!("dissociate {}", repo_path.display());
+    let output = Command::new("git")
+        .arg("repack")
+        .arg("-a")
+        .arg("-d")
+        .arg("--quiet")
+        .current_dir(&repo_path.to_path_buf())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::new(format!(
+            "Failed to repack {}: {}",
+            repo_path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    alternates::write(repo_path, &[])?;
+    Ok(true)
 }
 
-pub fn checkout_to_target(repo: &Repository, target: &str) -> Result<(), Error> {
+// Check out a branch or a commit. Local changes in the way are refused unless `force` is set.
+pub fn checkout_to_target(repo: &Repository, target: &str, force: bool) -> Result<(), Error> {
+    let mut checkout = CheckoutBuilder::new();
+    if force {
+        checkout.force();
+    }
+
     // Parse target as a branch
     if let Ok(branch) = repo.find_branch(target, git2::BranchType::Local) {
         let commit = branch.get().peel_to_commit()?;
         let object = commit.into_object();
-        repo.checkout_tree(&object, None)?;
+        repo.checkout_tree(&object, Some(&mut checkout))?;
         repo.set_head(branch.get().name().unwrap())?;
     } else {
         // If target is not a branch, try to parse it as a commit
         if let Ok(oid) = git2::Oid::from_str(target) {
             let object = repo.find_object(oid, Some(ObjectType::Commit))?;
-            repo.checkout_tree(&object, None)?;
+            repo.checkout_tree(&object, Some(&mut checkout))?;
             repo.set_head_detached(oid)?;
         } else {
             return Err(Error {
@@ -107,84 +129,714 @@ pub fn checkout_to_target(repo: &Repository, target: &str) -> Result<(), Error>
     Ok(())
 }
 
-pub fn get_remote_default_branch(url: &str, remote_name: Option<&str>) -> Option<String> {
-    let temp_dir = env::temp_dir();
-    let temp_repo_path = temp_dir.join("repo");
-    let repo = Repository::init_bare(temp_repo_path.to_str().unwrap()).ok()?;
+// Crane used to track branches with refs/for/<branch> and a HEAD detached at the same commit,
+// as git does not treat such a ref as a branch. Find the branch of a workspace in that layout.
+pub fn find_legacy_branch(repo: &Repository) -> Option<String> {
+    let head = repo.head().ok()?.target()?;
+    repo.references_glob("refs/for/*")
+        .ok()?
+        .flatten()
+        .filter(|reference| reference.target() == Some(head))
+        .filter_map(|reference| {
+            let name = reference.name()?.strip_prefix("refs/for/")?;
+            (name != "HEAD").then(|| name.to_string())
+        })
+        .next()
+}
 
-    let remote = match repo.find_remote(remote_name.unwrap_or("origin")) {
-        Ok(r) => r,
-        Err(_) => match repo.remote(remote_name?, url) {
-            Ok(remote) => remote,
-            Err(_) => return None,
-        },
+// Move refs/for/<branch> of the legacy layout to refs/heads/<branch> and attach HEAD to it.
+// Returns false if there is nothing to migrate.
+pub fn migrate_legacy_branch_ref(repo: &Repository, branch: &str) -> Result<bool, Error> {
+    let legacy_refname = format!("refs/for/{}", branch);
+    let mut legacy_ref = match repo.find_reference(&legacy_refname) {
+        Ok(reference) => reference,
+        Err(_) => return Ok(false),
     };
-    match remote.default_branch() {
-        Ok(branch) => Some(String::from_utf8(branch.to_vec()).unwrap()),
-        Err(_) => None,
+    let refname = format!("refs/heads/{}", branch);
+    if repo.find_reference(&refname).is_ok() {
+        log::warn!(
+            "keep {} as {} exists already in {}",
+            legacy_refname,
+            refname,
+            repo.path().display()
+        );
+        return Ok(false);
+    }
+
+    let head_on_legacy_ref = repo.find_reference("HEAD")?.symbolic_target()
+        == Some(legacy_refname.as_str())
+        || (repo.head_detached()? && repo.head()?.target() == legacy_ref.target());
+    log::debug!("migrate {} to {}", legacy_refname, refname);
+    legacy_ref.rename(&refname, false, &format!("Migrate from {}", legacy_refname))?;
+    if head_on_legacy_ref {
+        repo.set_head(&refname)?;
     }
+    Ok(true)
 }
 
-pub fn fetch_repository<'a>(
-    repo: &'a Repository,
-    url: &'a str,
-    refs: &[&str],
-    remote_name: Option<&str>,
-) -> Result<AnnotatedCommit<'a>, Error> {
-    log::debug!("set remote url to {}", url);
-    let remote_name = remote_name.unwrap_or("origin");
-    let mut remote = match repo.find_remote(remote_name) {
-        Ok(r) => r,
-        Err(_) => match repo.remote(remote_name, url) {
-            Ok(remote) => remote,
+// Make `branch` track the branch of the same name on `remote`
+pub fn set_branch_upstream(repo: &Repository, branch: &str, remote: &str) -> Result<(), Error> {
+    let mut config = repo.config()?;
+    config.set_str(&format!("branch.{}.remote", branch), remote)?;
+    config.set_str(
+        &format!("branch.{}.merge", branch),
+        &format!("refs/heads/{}", branch),
+    )?;
+    Ok(())
+}
+
+// Changes of tracked files in the index or the working tree. Untracked files are left
+// alone, the directories of nested components are untracked in their parent.
+pub fn has_local_changes(repo: &Repository) -> Result<bool, Error> {
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(false).include_ignored(false);
+    Ok(repo
+        .statuses(Some(&mut opts))?
+        .iter()
+        .any(|entry| entry.status() != Status::CURRENT))
+}
+
+// Commits known to be on the remote: the remote-tracking branches and the pinned commits
+fn pushed_tips(repo: &Repository) -> Result<Vec<Oid>, Error> {
+    let mut tips = vec![];
+    for glob in ["refs/remotes/*", &format!("{}*", CACHE_PINS_REF_PREFIX)] {
+        tips.extend(
+            repo.references_glob(glob)?
+                .flatten()
+                .filter_map(|reference| reference.target()),
+        );
+    }
+    Ok(tips)
+}
+
+// Count the commits of HEAD which are neither in `target`, in a remote-tracking branch or
+// pinned commit, nor in `upstream`, a repository holding what was fetched from the remote
+pub fn count_unpushed_commits(
+    repo: &Repository,
+    target: Oid,
+    upstream: Option<&Repository>,
+) -> Result<usize, Error> {
+    let head = match repo.head().ok().and_then(|head| head.target()) {
+        Some(head) => head,
+        None => return Ok(0),
+    };
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head)?;
+    revwalk.hide(target)?;
+    for tip in pushed_tips(repo)? {
+        revwalk.hide(tip)?;
+    }
+    let mut unpushed = 0;
+    for oid in revwalk {
+        let oid = oid?;
+        if upstream.is_none_or(|upstream| upstream.find_commit(oid).is_err()) {
+            unpushed += 1;
+        }
+    }
+    Ok(unpushed)
+}
+
+// Local branches, and HEAD if it is detached, with commits which are neither in `upstream`,
+// a repository holding what was fetched from the remote, nor in a remote-tracking branch or
+// pinned commit
+pub fn find_unpushed_refs(
+    repo: &Repository,
+    upstream: Option<&Repository>,
+) -> Result<Vec<String>, Error> {
+    let mut refs = vec![];
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        if let (Some(name), Some(oid)) = (branch.name()?, branch.get().target()) {
+            refs.push((name.to_string(), oid));
+        }
+    }
+    if repo.head_detached()? {
+        if let Some(oid) = repo.head()?.target() {
+            refs.push(("HEAD".to_string(), oid));
+        }
+    }
+
+    let remote_tips = pushed_tips(repo)?;
+    let mut unpushed = vec![];
+    for (name, oid) in refs {
+        if upstream.is_some_and(|upstream| upstream.find_commit(oid).is_ok()) {
+            continue;
+        }
+        let mut pushed = false;
+        for tip in &remote_tips {
+            if is_commit_on_branch(repo, oid, *tip)? {
+                pushed = true;
+                break;
+            }
+        }
+        if !pushed {
+            unpushed.push(name);
+        }
+    }
+    Ok(unpushed)
+}
+
+// Whether `commit` is `tip` or one of its ancestors, a commit which was not fetched with
+// the branch is not on it
+pub fn is_commit_on_branch(repo: &Repository, commit: Oid, tip: Oid) -> Result<bool, Error> {
+    if commit == tip {
+        return Ok(true);
+    }
+    if repo.find_commit(commit).is_err() {
+        return Ok(false);
+    }
+    Ok(repo.graph_descendant_of(tip, commit)?)
+}
+
+// The signature from git config, or a placeholder if the user has not configured one
+pub fn default_signature(repo: &Repository) -> Result<Signature<'static>, Error> {
+    Ok(repo
+        .signature()
+        .or_else(|_| Signature::now("crane", "crane@localhost"))?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebaseResult {
+    // Number of commits replayed onto the target
+    Rebased(usize),
+    // The rebase stopped at a conflict and is left in progress for the user to resolve
+    Conflicts,
+}
+
+// Replay the commits of HEAD which are not in `target` onto `target`
+pub fn rebase_onto(repo: &Repository, target: Oid) -> Result<RebaseResult, Error> {
+    let upstream = repo.find_annotated_commit(target)?;
+    let signature = default_signature(repo)?;
+    let mut rebase = repo.rebase(None, Some(&upstream), None, None)?;
+    let mut rebased = 0;
+    while let Some(operation) = rebase.next() {
+        operation?;
+        if repo.index()?.has_conflicts() {
+            return Ok(RebaseResult::Conflicts);
+        }
+        match rebase.commit(None, &signature, None) {
+            Ok(_) => rebased += 1,
+            // The change is in the target already
+            Err(err) if err.code() == ErrorCode::Applied => {}
             Err(err) => return Err(err.into()),
+        }
+    }
+    rebase.finish(Some(&signature))?;
+    Ok(RebaseResult::Rebased(rebased))
+}
+
+// Stash the changes of tracked files, untracked files are left alone as the directories
+// of nested components are untracked in their parent
+pub fn stash_local_changes(repo: &mut Repository, message: &str) -> Result<Oid, Error> {
+    let signature = default_signature(repo)?;
+    Ok(repo.stash_save(&signature, message, Some(StashFlags::DEFAULT))?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnstashResult {
+    Reapplied,
+    // The stash was applied with conflicts and is kept as well
+    Conflicts,
+    // Applying the stash failed and left the worktree untouched, e.g. at a checkout conflict
+    Failed,
+}
+
+// Re-apply the latest stash and drop it, unless applying it failed or left conflicts
+pub fn unstash_local_changes(repo: &mut Repository) -> Result<UnstashResult, Error> {
+    if let Err(err) = repo.stash_apply(0, None) {
+        log::warn!("failed to re-apply stash: {}", err);
+        return Ok(UnstashResult::Failed);
+    }
+    if repo.index()?.has_conflicts() {
+        return Ok(UnstashResult::Conflicts);
+    }
+    repo.stash_drop(0)?;
+    Ok(UnstashResult::Reapplied)
+}
+
+pub fn get_remote_default_branch(url: &str) -> Option<String> {
+    let policy = FetchPolicy::from_config(&UserConfig::instance());
+    retry(
+        &policy,
+        &format!("get the default branch of {}", url),
+        || {
+            with_fallback(url, |fetch_url| {
+                connect_watched(fetch_url, &policy, |connection| {
+                    let branch = connection.default_branch()?;
+                    Ok(String::from_utf8_lossy(&branch).to_string())
+                })
+            })
         },
+    )
+    .map_err(|e| log::warn!("failed to get the default branch of {}: {}", url, e))
+    .ok()
+    .map(|branch| branch.trim_start_matches("refs/heads/").to_string())
+}
+
+// Resolve the proxy used to fetch from a url with the precedence of git:
+//   http.<url>.proxy > http.proxy > <scheme>_proxy > all_proxy
+// Hosts matching an entry of no_proxy never go through a proxy.
+pub fn resolve_proxy<F>(url: &GitUrl, git_config: Option<&Config>, getenv: F) -> Option<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let scheme = match url.proxy_scheme() {
+        Some(scheme) => scheme,
+        None => {
+            log::debug!("fetch {} without proxy: not a http(s) url", url);
+            return None;
+        }
+    };
+    let getenv_any = |names: &[&str]| {
+        names.iter().find_map(|name| {
+            getenv(name)
+                .filter(|v| !v.is_empty())
+                .map(|v| (v, name.to_string()))
+        })
     };
 
-    let mut fetch_option = FetchOptions::new();
+    let (proxy, source) = git_config
+        .and_then(|config| get_config_proxy(config, url))
+        .or_else(|| {
+            getenv_any(&[
+                &format!("{}_proxy", scheme),
+                &format!("{}_PROXY", scheme.to_uppercase()),
+                "all_proxy",
+                "ALL_PROXY",
+            ])
+        })?;
 
-    // Set up proxy
-    let parsed_url = match Url::parse(&url) {
-        Ok(u) => u,
-        Err(_) => {
-            return Err(Error {
-                message: format!("invalid remote url {}", url),
-            })
+    // An empty proxy in git config disables the proxy from environment variables
+    if proxy.is_empty() {
+        log::debug!("fetch {} without proxy: disabled by {}", url, source);
+        return None;
+    }
+
+    let host = url.host.clone().unwrap_or_default();
+    if let Some((no_proxy, _)) = getenv_any(&["no_proxy", "NO_PROXY"]) {
+        if let Some(entry) = match_no_proxy(&no_proxy, &host, url.port) {
+            log::debug!(
+                "fetch {} without proxy: matches no_proxy entry {}",
+                url,
+                entry
+            );
+            return None;
         }
+    }
+
+    log::info!("fetch {} through proxy {} (from {})", url, proxy, source);
+    Some(proxy)
+}
+
+// Find the proxy set by the most specific http.<url>.proxy matching the url, or by http.proxy
+fn get_config_proxy(config: &Config, url: &GitUrl) -> Option<(String, String)> {
+    let mut best: Option<(String, String)> = None;
+    if let Ok(mut entries) = config.entries(Some(r"^http\..+\.proxy$")) {
+        while let Some(Ok(entry)) = entries.next() {
+            let (name, value) = match (entry.name(), entry.value()) {
+                (Some(name), Some(value)) => (name.to_string(), value.to_string()),
+                _ => continue,
+            };
+            let pattern = &name["http.".len()..name.len() - ".proxy".len()];
+            let more_specific = best.as_ref().is_none_or(|(_, n)| name.len() > n.len());
+            if url_matches_config(pattern, url) && more_specific {
+                best = Some((value, name));
+            }
+        }
+    }
+    best.or_else(|| {
+        config
+            .get_string("http.proxy")
+            .ok()
+            .map(|proxy| (proxy, String::from("http.proxy")))
+    })
+}
+
+// Check if a url matches the <url> part of a "http.<url>.*" git config
+fn url_matches_config(pattern: &str, url: &GitUrl) -> bool {
+    let (scheme, rest) = match pattern.split_once("://") {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let rest = rest.split_once('@').map_or(rest, |(_, r)| r);
+    let (authority, path) = rest.find('/').map_or((rest, ""), |i| rest.split_at(i));
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>().ok()),
+        None => (authority, None),
+    };
+
+    let url_host = url.host.clone().unwrap_or_default().to_lowercase();
+    let host = host.to_lowercase();
+    let host_matched = match host.strip_prefix("*.") {
+        Some(domain) => url_host.ends_with(&format!(".{}", domain)),
+        None => url_host == host,
+    };
+    let path = path.trim_end_matches('/');
+    let path_matched =
+        path.is_empty() || url.path == path || url.path.starts_with(&format!("{}/", path));
+
+    scheme == url.scheme && host_matched && (port.is_none() || port == url.port) && path_matched
+}
+
+// Find the entry of no_proxy matching a host. The entries are separated by commas and can be
+// "*", host names, domain suffixes, IP addresses or CIDR ranges, optionally followed by a port.
+pub fn match_no_proxy<'a>(no_proxy: &'a str, host: &str, port: Option<u16>) -> Option<&'a str> {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_lowercase();
+    no_proxy
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty())
+        .find(|entry| no_proxy_entry_matches(entry, &host, port))
+}
+
+fn no_proxy_entry_matches(entry: &str, host: &str, port: Option<u16>) -> bool {
+    if entry == "*" {
+        return true;
+    }
+    if let Some((network, prefix_len)) = entry.split_once('/') {
+        return cidr_contains(network, prefix_len, host);
+    }
+
+    let (entry_host, entry_port) = match entry.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((ip, port)) => (ip, port.strip_prefix(':')),
+            None => (rest, None),
+        },
+        None => match entry.split_once(':') {
+            Some((host, port)) if !port.contains(':') => (host, Some(port)),
+            _ => (entry, None),
+        },
+    };
+    if entry_port.is_some() && entry_port.and_then(|p| p.parse::<u16>().ok()) != port {
+        return false;
+    }
+
+    let entry_host = entry_host.trim_start_matches('*').trim_start_matches('.');
+    let entry_host = entry_host.to_lowercase();
+    host == entry_host || host.ends_with(&format!(".{}", entry_host))
+}
+
+fn cidr_contains(network: &str, prefix_len: &str, host: &str) -> bool {
+    let (network, prefix_len, addr) = match (
+        network.parse::<IpAddr>(),
+        prefix_len.parse::<u32>(),
+        host.parse::<IpAddr>(),
+    ) {
+        (Ok(network), Ok(prefix_len), Ok(addr)) => (network, prefix_len, addr),
+        _ => return false,
     };
-    let schema = parsed_url.scheme().to_owned();
-    match env::var(schema.clone() + "_proxy").or(env::var(schema.clone().to_uppercase() + "_PROXY"))
-    {
-        Ok(proxy_url) => {
-            log::debug!("operations will be under a proxy: {}", proxy_url);
-            let mut proxy_option = ProxyOptions::new();
-            proxy_option.url(&proxy_url);
-            fetch_option.proxy_options(proxy_option);
+    match (network, addr) {
+        (IpAddr::V4(network), IpAddr::V4(addr)) if prefix_len <= 32 => {
+            let shift = 32 - prefix_len;
+            prefix_len == 0 || u32::from(network) >> shift == u32::from(addr) >> shift
         }
-        Err(_) => {}
+        (IpAddr::V6(network), IpAddr::V6(addr)) if prefix_len <= 128 => {
+            let shift = 128 - prefix_len;
+            prefix_len == 0 || u128::from(network) >> shift == u128::from(addr) >> shift
+        }
+        _ => false,
+    }
+}
+
+// The remote named `remote_name`, it is added with `url` if it does not exist
+pub fn ensure_remote<'a>(
+    repo: &'a Repository,
+    remote_name: &str,
+    url: &str,
+) -> Result<Remote<'a>, Error> {
+    let git_url = GitUrl::parse(url)?;
+    log::debug!("set remote url to {}", git_url);
+    match repo.find_remote(remote_name) {
+        Ok(r) => Ok(r),
+        Err(_) => Ok(repo.remote(remote_name, &git_url.fetch_url())?),
+    }
+}
+
+// Create the remote or point it at `url`, and set its push url if one is given
+pub fn configure_remote(
+    repo: &Repository,
+    remote_name: &str,
+    url: &str,
+    push_url: Option<&str>,
+) -> Result<(), Error> {
+    let fetch_url = GitUrl::parse(url)?.fetch_url();
+    if repo.find_remote(remote_name).is_err() {
+        log::debug!("add remote {} at {}", remote_name, fetch_url);
+        repo.remote(remote_name, &fetch_url)?;
+    } else if changed_remote_url(repo, remote_name, url)?.is_some() {
+        log::debug!("point remote {} at {}", remote_name, fetch_url);
+        repo.remote_set_url(remote_name, &fetch_url)?;
+    }
+    if let Some(push_url) = push_url {
+        let push_url = GitUrl::parse(push_url)?.fetch_url();
+        let current = repo
+            .find_remote(remote_name)?
+            .pushurl()
+            .map(|u| u.to_string());
+        if current.as_deref() != Some(push_url.as_str()) {
+            repo.remote_set_pushurl(remote_name, Some(&push_url))?;
+        }
+    }
+    Ok(())
+}
+
+// The url of the remote if it is not `url`, spellings of the same url are not a change
+pub fn changed_remote_url(
+    repo: &Repository,
+    remote_name: &str,
+    url: &str,
+) -> Result<Option<String>, Error> {
+    let current = match repo.find_remote(remote_name) {
+        Ok(remote) => remote.url().map(|u| u.to_string()),
+        Err(_) => None,
+    };
+    let current = match current {
+        Some(current) => current,
+        None => return Ok(None),
+    };
+    let canonical = GitUrl::parse(url)?.canonical();
+    match GitUrl::parse(&current) {
+        Ok(current_url) if current_url.canonical() == canonical => Ok(None),
+        _ => Ok(Some(current)),
+    }
+}
+
+fn proxy_options(git_url: &GitUrl, git_config: Option<&Config>) -> ProxyOptions<'static> {
+    let mut proxy_option = ProxyOptions::new();
+    if let Some(proxy_url) = resolve_proxy(git_url, git_config, |name| env::var(name).ok()) {
+        proxy_option.url(&proxy_url);
     }
+    proxy_option
+}
 
-    // Set up authentication
+fn credential_callbacks() -> Result<RemoteCallbacks<'static>, Error> {
+    let mut credentials = CredentialChain::from_config(&UserConfig::instance())?;
     let mut callbacks = RemoteCallbacks::new();
-    callbacks.credentials(|_url, username_from_url, _allowed_types| {
-        git2::Cred::ssh_key(
-            username_from_url.unwrap(),
-            Some(home::home_dir().unwrap().join(".ssh/id_rsa.pub").as_path()),
-            home::home_dir().unwrap().join(".ssh/id_rsa").as_path(),
-            None,
-        )
+    callbacks.credentials(move |url, username_from_url, allowed_types| {
+        credentials.next_credential(url, username_from_url, allowed_types)
     });
-    fetch_option.remote_callbacks(callbacks);
+    Ok(callbacks)
+}
+
+// The branches of `url` and their tips, like "git ls-remote --heads"
+pub fn list_remote_heads(url: &str) -> Result<Vec<(String, Oid)>, Error> {
+    log::debug!("list heads of {}", url);
+    let policy = FetchPolicy::from_config(&UserConfig::instance());
+    Ok(retry(&policy, &format!("list heads of {}", url), || {
+        with_fallback(url, |fetch_url| {
+            connect_watched(fetch_url, &policy, |connection| {
+                Ok(connection
+                    .list()?
+                    .iter()
+                    .filter_map(|head| {
+                        let branch = head.name().strip_prefix("refs/heads/")?;
+                        Some((branch.to_string(), head.oid()))
+                    })
+                    .collect())
+            })
+        })
+    })?)
+}
+
+// Connect to `url` for fetching and run `op` with the connection, watched for the timeouts of
+// `policy`. Nothing is written, so one which timed out is left behind.
+fn connect_watched<T: Send + 'static>(
+    url: &str,
+    policy: &FetchPolicy,
+    op: fn(&RemoteConnection) -> Result<T, git2::Error>,
+) -> Result<T, git2::Error> {
+    let git_url = GitUrl::parse(url).map_err(|e| git2::Error::from_str(&e.message))?;
+    run_watched(url, policy, true, move |watch| {
+        let mut remote = Remote::create_detached(git_url.fetch_url().as_str())?;
+        let git_config = Config::open_default().ok();
+        let connection = remote.connect_auth(
+            Direction::Fetch,
+            Some(watch.callbacks()?),
+            Some(proxy_options(&git_url, git_config.as_ref())),
+        )?;
+        op(&connection)
+    })
+}
+
+pub fn fetch_repository<'a>(
+    repo: &'a Repository,
+    url: &'a str,
+    refs: &[&str],
+    remote_name: Option<&str>,
+) -> Result<AnnotatedCommit<'a>, Error> {
+    let remote_name = remote_name.unwrap_or("origin");
+    ensure_remote(repo, remote_name, url)?;
+    let policy = FetchPolicy::from_config(&UserConfig::instance());
 
-    log::debug!("fetch refspec \"{:?}\"", refs);
-    // Check if the refspec is valid
-    remote.fetch(refs, Some(&mut fetch_option), None)?;
-    remote.disconnect()?;
+    // Every mirror is tried before retrying, the remote keeps its url and a rewritten url or
+    // a mirror is fetched from anonymously
+    retry(&policy, &format!("fetch {}", url), || {
+        with_fallback(url, |fetch_url| {
+            let remote = match fetch_url == url {
+                true => FetchRemote::Named(remote_name.to_string()),
+                false => FetchRemote::Anonymous(fetch_url.to_string()),
+            };
+            fetch_watched(repo.path(), remote, fetch_url, refs, &policy)
+        })
+    })?;
 
     let fetch_head = repo.find_reference("FETCH_HEAD")?;
     Ok(repo.reference_to_annotated_commit(&fetch_head)?)
 }
 
+enum FetchRemote {
+    Named(String),
+    Anonymous(String),
+}
+
+// Fetch in a thread watched for the timeouts of `policy`. The fetch writes to the repository,
+// so one which timed out is waited for: it never runs along with the next attempt and the
+// lock of the repository is held until it stopped.
+fn fetch_watched(
+    git_dir: &Path,
+    remote: FetchRemote,
+    url: &str,
+    refs: &[&str],
+    policy: &FetchPolicy,
+) -> Result<(), git2::Error> {
+    let git_url = GitUrl::parse(url).map_err(|e| git2::Error::from_str(&e.message))?;
+    let git_dir = git_dir.to_path_buf();
+    let refs: Vec<String> = refs.iter().map(|r| r.to_string()).collect();
+    run_watched(url, policy, false, move |watch| {
+        let repo = Repository::open(&git_dir)?;
+        let mut remote = match &remote {
+            FetchRemote::Named(name) => repo.find_remote(name)?,
+            FetchRemote::Anonymous(url) => repo.remote_anonymous(url)?,
+        };
+        let mut fetch_option = FetchOptions::new();
+        let git_config = repo.config().ok();
+        fetch_option.proxy_options(proxy_options(&git_url, git_config.as_ref()));
+        fetch_option.remote_callbacks(watch.callbacks()?);
+
+        log::debug!("fetch refspec \"{:?}\"", refs);
+        remote.fetch(&refs, Some(&mut fetch_option), None)?;
+        remote.disconnect()
+    })
+}
+
+// Progress of an operation run by `run_watched`
+#[derive(Clone)]
+struct Watch {
+    start: Instant,
+    // Milliseconds since `start` of the last progress, none until the remote responds
+    last_progress: Arc<AtomicU64>,
+    aborted: Arc<AtomicBool>,
+}
+
+impl Watch {
+    fn new() -> Self {
+        Watch {
+            start: Instant::now(),
+            last_progress: Arc::new(AtomicU64::new(u64::MAX)),
+            aborted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn record_progress(&self) {
+        self.last_progress
+            .store(self.start.elapsed().as_millis() as u64, Ordering::SeqCst);
+    }
+
+    fn aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    // Callbacks recording any change of the transfer as progress, resolving deltas included,
+    // and stopping libgit2 once aborted
+    fn callbacks(&self) -> Result<RemoteCallbacks<'static>, git2::Error> {
+        let mut callbacks =
+            credential_callbacks().map_err(|e| git2::Error::from_str(&e.message))?;
+        let watch = self.clone();
+        let mut last_stats = None;
+        callbacks.transfer_progress(move |stats| {
+            let current = (
+                stats.received_objects(),
+                stats.indexed_objects(),
+                stats.indexed_deltas(),
+                stats.received_bytes(),
+            );
+            if last_stats.replace(current) != Some(current) {
+                watch.record_progress();
+            }
+            !watch.aborted()
+        });
+        let watch = self.clone();
+        callbacks.sideband_progress(move |_| {
+            watch.record_progress();
+            !watch.aborted()
+        });
+        Ok(callbacks)
+    }
+}
+
+// Run `op` on `url` in a thread watched for the timeouts of `policy`, libgit2 has none. An
+// operation which timed out is aborted as soon as libgit2 gets back to its callbacks, it is
+// waited for unless `detach`, which only suits operations writing nothing.
+fn run_watched<T: Send + 'static>(
+    url: &str,
+    policy: &FetchPolicy,
+    detach: bool,
+    op: impl FnOnce(&Watch) -> Result<T, git2::Error> + Send + 'static,
+) -> Result<T, git2::Error> {
+    let watch = Watch::new();
+    let (sender, receiver) = mpsc::channel();
+    let op_watch = watch.clone();
+    thread::spawn(move || {
+        // The receiver is gone if the operation timed out and was detached
+        let _ = sender.send(op(&op_watch));
+    });
+
+    loop {
+        match receiver.recv_timeout(WATCH_INTERVAL) {
+            Ok(result) => return result,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(git2::Error::from_str("the git thread panicked"))
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        let message = match watch.last_progress.load(Ordering::SeqCst) {
+            u64::MAX if watch.start.elapsed() > policy.connect_timeout => format!(
+                "{} did not respond within {}s",
+                url,
+                policy.connect_timeout.as_secs()
+            ),
+            u64::MAX => continue,
+            millis
+                if watch
+                    .start
+                    .elapsed()
+                    .saturating_sub(Duration::from_millis(millis))
+                    > policy.stall_timeout =>
+            {
+                format!(
+                    "received nothing from {} for {}s",
+                    url,
+                    policy.stall_timeout.as_secs()
+                )
+            }
+            _ => continue,
+        };
+        watch.aborted.store(true, Ordering::SeqCst);
+        if !detach {
+            log::warn!("{}, waiting for the git operation to stop", message);
+            // Whatever it ended with, the attempt timed out
+            let _ = receiver.recv();
+        }
+        return Err(git2::Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Net,
+            message,
+        ));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -218,9 +870,142 @@ mod tests {
             get_repo_name("file:///tmp/repo/.git"),
             Some("repo".to_string())
         );
+        assert_eq!(
+            get_repo_name("git@github.com:user/repo.git"),
+            Some("repo".to_string())
+        );
+        assert_eq!(
+            get_repo_name("ssh://git@github.com/user/repo.git"),
+            Some("repo".to_string())
+        );
+        assert_eq!(get_repo_name("/srv/git/repo.git"), Some("repo".to_string()));
         assert_eq!(get_repo_name("not a url"), None);
     }
 
+    fn getenv<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |name| {
+            vars.iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn test_resolve_proxy_from_env() {
+        let url = GitUrl::parse("https://github.com/org/repo.git").unwrap();
+        let vars = [
+            ("HTTPS_PROXY", "http://upper:3128"),
+            ("all_proxy", "http://all:3128"),
+        ];
+        assert_eq!(
+            resolve_proxy(&url, None, getenv(&vars)),
+            Some("http://upper:3128".to_string())
+        );
+
+        let vars = [
+            ("https_proxy", "http://lower:3128"),
+            ("HTTPS_PROXY", "http://upper:3128"),
+        ];
+        assert_eq!(
+            resolve_proxy(&url, None, getenv(&vars)),
+            Some("http://lower:3128".to_string())
+        );
+
+        let vars = [
+            ("http_proxy", "http://http:3128"),
+            ("ALL_PROXY", "http://all:3128"),
+        ];
+        assert_eq!(
+            resolve_proxy(&url, None, getenv(&vars)),
+            Some("http://all:3128".to_string())
+        );
+
+        let ssh_url = GitUrl::parse("git@github.com:org/repo.git").unwrap();
+        assert_eq!(resolve_proxy(&ssh_url, None, getenv(&vars)), None);
+        assert_eq!(resolve_proxy(&url, None, getenv(&[])), None);
+    }
+
+    #[test]
+    fn test_resolve_proxy_with_no_proxy() {
+        let vars = [
+            ("https_proxy", "http://proxy:3128"),
+            (
+                "no_proxy",
+                "localhost, .corp.example.com,10.0.0.0/8,git.internal:8443",
+            ),
+        ];
+        let resolve = |url: &str| resolve_proxy(&GitUrl::parse(url).unwrap(), None, getenv(&vars));
+
+        assert_eq!(resolve("https://git.corp.example.com/repo.git"), None);
+        assert_eq!(resolve("https://corp.example.com/repo.git"), None);
+        assert_eq!(resolve("https://10.1.2.3/repo.git"), None);
+        assert_eq!(resolve("https://git.internal:8443/repo.git"), None);
+        assert_eq!(
+            resolve("https://git.internal/repo.git"),
+            Some("http://proxy:3128".to_string())
+        );
+        assert_eq!(
+            resolve("https://11.1.2.3/repo.git"),
+            Some("http://proxy:3128".to_string())
+        );
+        assert_eq!(
+            resolve("https://github.com/repo.git"),
+            Some("http://proxy:3128".to_string())
+        );
+    }
+
+    #[test]
+    fn test_match_no_proxy() {
+        assert_eq!(match_no_proxy("*", "github.com", None), Some("*"));
+        assert_eq!(
+            match_no_proxy("example.com", "www.example.com", None),
+            Some("example.com")
+        );
+        assert_eq!(match_no_proxy("example.com", "badexample.com", None), None);
+        assert_eq!(
+            match_no_proxy("fd00::/8", "[fd12::1]", None),
+            Some("fd00::/8")
+        );
+        assert_eq!(
+            match_no_proxy("192.168.1.1", "192.168.1.1", None),
+            Some("192.168.1.1")
+        );
+        assert_eq!(match_no_proxy("192.168.0.0/16", "192.169.0.1", None), None);
+    }
+
+    #[test]
+    fn test_resolve_proxy_from_git_config() {
+        let temp_dir = TempDir::new("git_config").expect("Failed to create temporary directory");
+        let mut config = Config::open(&temp_dir.path().join("config")).unwrap();
+        config.set_str("http.proxy", "http://default:3128").unwrap();
+        config
+            .set_str("http.https://github.com.proxy", "http://github:3128")
+            .unwrap();
+        config
+            .set_str("http.https://github.com/org.proxy", "http://org:3128")
+            .unwrap();
+        config
+            .set_str("http.https://*.internal.com.proxy", "")
+            .unwrap();
+
+        let vars = [("https_proxy", "http://env:3128")];
+        let resolve =
+            |url: &str| resolve_proxy(&GitUrl::parse(url).unwrap(), Some(&config), getenv(&vars));
+        assert_eq!(
+            resolve("https://github.com/org/repo.git"),
+            Some("http://org:3128".to_string())
+        );
+        assert_eq!(
+            resolve("https://github.com/other/repo.git"),
+            Some("http://github:3128".to_string())
+        );
+        assert_eq!(
+            resolve("https://gitlab.com/org/repo.git"),
+            Some("http://default:3128".to_string())
+        );
+        assert_eq!(resolve("https://git.internal.com/org/repo.git"), None);
+    }
+
     #[test]
     fn test_fetch_repository_positive() {
         let remote_repo_dir =
@@ -241,4 +1026,200 @@ mod tests {
 
         // The temporary directory will be automatically deleted when `temp_dir` goes out of scope
     }
+
+    #[test]
+    fn test_list_remote_heads() {
+        let remote_repo_dir =
+            TempDir::new("remote_repo").expect("Failed to create temporary directory");
+        let repo_url = test_utils::create_git_repo_in_dir(
+            remote_repo_dir.path(),
+            &PathBuf::from("test.txt"),
+            "Hello, world!",
+        )
+        .unwrap();
+        let head = Repository::open(remote_repo_dir.path())
+            .unwrap()
+            .head()
+            .unwrap()
+            .target()
+            .unwrap();
+
+        let heads = list_remote_heads(&repo_url).unwrap();
+        assert_eq!(heads, vec![("main".to_string(), head)]);
+    }
+
+    #[test]
+    fn test_changed_remote_url() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        assert_eq!(
+            changed_remote_url(&repo, "origin", "https://github.com/org/repo.git").unwrap(),
+            None
+        );
+
+        repo.remote("origin", "https://github.com/org/repo.git")
+            .unwrap();
+        assert_eq!(
+            changed_remote_url(&repo, "origin", "https://GitHub.com/org/repo.git/").unwrap(),
+            None
+        );
+        assert_eq!(
+            changed_remote_url(&repo, "origin", "https://gitlab.com/org/repo.git").unwrap(),
+            Some("https://github.com/org/repo.git".to_string())
+        );
+    }
+
+    #[test]
+    fn test_dissociate() {
+        let cache_repo_dir =
+            TempDir::new("cache_repo").expect("Failed to create temporary directory");
+        test_utils::create_git_repo_in_dir(
+            cache_repo_dir.path(),
+            &PathBuf::from("test.txt"),
+            "Hello, world!",
+        )
+        .unwrap();
+        let head = Repository::open(cache_repo_dir.path())
+            .unwrap()
+            .head()
+            .unwrap()
+            .target()
+            .unwrap();
+
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        alternates::add(temp_dir.path(), &cache_repo_dir.path().join(".git")).unwrap();
+        repo.reference("refs/heads/main", head, true, "borrowed")
+            .unwrap();
+        assert!(alternates::missing(temp_dir.path()).unwrap().is_empty());
+
+        assert!(dissociate(temp_dir.path()).unwrap());
+        assert!(alternates::read(temp_dir.path()).unwrap().is_empty());
+        assert!(!dissociate(temp_dir.path()).unwrap());
+
+        // The objects are still there once the cache repository is gone
+        let cache_git_dir = cache_repo_dir.path().join(".git");
+        drop(cache_repo_dir);
+        assert!(!cache_git_dir.exists());
+        let repo = Repository::open(temp_dir.path()).unwrap();
+        assert!(repo.find_commit(head).is_ok());
+    }
+
+    #[test]
+    fn test_is_commit_on_branch() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        let file = PathBuf::from("test.txt");
+        test_utils::create_git_repo_in_dir(temp_dir.path(), &file, "Hello, world!").unwrap();
+        let repo = Repository::open(temp_dir.path()).unwrap();
+        let first = repo.head().unwrap().target().unwrap();
+        test_utils::modify_file_in_repo(temp_dir.path(), &file, "Bye", false, true, true).unwrap();
+        let tip = repo.head().unwrap().target().unwrap();
+
+        assert!(is_commit_on_branch(&repo, tip, tip).unwrap());
+        assert!(is_commit_on_branch(&repo, first, tip).unwrap());
+        assert!(!is_commit_on_branch(&repo, tip, first).unwrap());
+        let unknown = Oid::from_str("0123456789abcdef0123456789abcdef01234567").unwrap();
+        assert!(!is_commit_on_branch(&repo, unknown, tip).unwrap());
+    }
+
+    #[test]
+    fn test_find_unpushed_refs() {
+        let origin_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        let file = PathBuf::from("test.txt");
+        test_utils::create_git_repo_in_dir(origin_dir.path(), &file, "Hello, world!").unwrap();
+        let origin = Repository::open(origin_dir.path()).unwrap();
+        assert_eq!(find_unpushed_refs(&origin, None).unwrap(), vec!["main"]);
+        assert!(find_unpushed_refs(&origin, Some(&origin))
+            .unwrap()
+            .is_empty());
+
+        let clone_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        let clone =
+            Repository::clone(origin_dir.path().to_str().unwrap(), clone_dir.path()).unwrap();
+        assert!(find_unpushed_refs(&clone, None).unwrap().is_empty());
+        test_utils::modify_file_in_repo(clone_dir.path(), &file, "Bye", false, true, true).unwrap();
+        assert_eq!(
+            find_unpushed_refs(&clone, Some(&origin)).unwrap(),
+            vec!["main"]
+        );
+    }
+
+    #[test]
+    fn test_unstash_local_changes() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        let file = PathBuf::from("test.txt");
+        test_utils::create_git_repo_in_dir(temp_dir.path(), &file, "Hello, world!").unwrap();
+        let mut repo = Repository::open(temp_dir.path()).unwrap();
+
+        std::fs::write(temp_dir.path().join(&file), "Bye").unwrap();
+        stash_local_changes(&mut repo, "test").unwrap();
+        assert_eq!(
+            unstash_local_changes(&mut repo).unwrap(),
+            UnstashResult::Reapplied
+        );
+
+        // A change of the same file in the worktree prevents applying the stash
+        stash_local_changes(&mut repo, "test").unwrap();
+        std::fs::write(temp_dir.path().join(&file), "Hi").unwrap();
+        assert_eq!(
+            unstash_local_changes(&mut repo).unwrap(),
+            UnstashResult::Failed
+        );
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join(&file)).unwrap(),
+            "Hi"
+        );
+        let mut stashes = 0;
+        repo.stash_foreach(|_, _, _| {
+            stashes += 1;
+            true
+        })
+        .unwrap();
+        // The stash re-applied first was dropped
+        assert_eq!(stashes, 1);
+    }
+
+    #[test]
+    fn test_dissociate_with_missing_alternate() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        Repository::init(temp_dir.path()).unwrap();
+        alternates::add(temp_dir.path(), &temp_dir.path().join("missing/.git")).unwrap();
+        assert!(dissociate(temp_dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_fetch_connect_timeout() {
+        // Accepts the connection but closes it without answering after a while
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("git://{}/repo.git", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(3));
+            drop(stream);
+        });
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        let repo = Repository::init_bare(temp_dir.path()).unwrap();
+        let policy = FetchPolicy {
+            connect_timeout: Duration::from_secs(1),
+            ..FetchPolicy::from_config(&UserConfig::default())
+        };
+
+        let start = Instant::now();
+        let error = fetch_watched(
+            repo.path(),
+            FetchRemote::Anonymous(url.clone()),
+            &url,
+            &["refs/heads/main"],
+            &policy,
+        )
+        .unwrap_err();
+        assert_eq!(error.class(), ErrorClass::Net);
+        assert_eq!(
+            error.message(),
+            format!("{} did not respond within 1s", url)
+        );
+        // The aborted fetch stopped before returning
+        assert!(start.elapsed() >= Duration::from_secs(3));
+        server.join().unwrap();
+    }
 }
@@ -0,0 +1,32 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use colored::Colorize;
+
+// Exit code of a process killed by SIGINT
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Handle Ctrl-C instead of dying in the middle of a checkout: the first one lets the running
+// work finish and stops starting new work, the second one exits right away.
+// Must be called within a tokio runtime.
+pub fn install_handler() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                println!("{}", "Interrupted again, exiting".red());
+                process::exit(INTERRUPTED_EXIT_CODE);
+            }
+            println!(
+                "{}",
+                "Interrupted, finishing the components in progress, press Ctrl-C again to exit"
+                    .yellow()
+            );
+        }
+    });
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,229 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::errors::Error;
+
+pub const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+// A lock file whose content can not be parsed is considered stale after this duration,
+// the owner might have crashed before writing its pid
+const INCOMPLETE_LOCK_AGE: Duration = Duration::from_secs(10);
+
+// A lock shared between processes, backed by a file containing the pid of its owner.
+// The lock is released when the value is dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub since: u64,
+    // Command line of the owner, missing in locks of older versions
+    pub command: Option<String>,
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pid {}", self.pid)?;
+        if let Some(command) = &self.command {
+            write!(f, " ({})", command)?;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        write!(f, " for {}s", now.as_secs().saturating_sub(self.since))
+    }
+}
+
+impl FileLock {
+    // Acquire the lock in `path`, waiting up to `timeout` while it is held by a live process.
+    // `description` names the lock in the messages shown to the user, e.g. "cache lock of <url>".
+    pub fn acquire(path: &Path, description: &str, timeout: Duration) -> Result<FileLock, Error> {
+        let start = Instant::now();
+        let mut reported_holder = None;
+
+        loop {
+            if let Some(lock) = Self::try_acquire(path)? {
+                return Ok(lock);
+            }
+
+            let holder = read_holder(path);
+            if let Some(holder) = &holder {
+                if reported_holder != Some(holder.pid) {
+                    println!("Waiting for {} held by pid {}", description, holder.pid);
+                    reported_holder = Some(holder.pid);
+                }
+            }
+            if start.elapsed() >= timeout {
+                return Err(Error::new(format!(
+                    "Timed out after {}s waiting for {} held by pid {}",
+                    timeout.as_secs(),
+                    description,
+                    holder.map_or(String::from("unknown"), |h| h.pid.to_string())
+                )));
+            }
+            thread::sleep(RETRY_INTERVAL);
+        }
+    }
+
+    // Acquire the lock in `path` if it is free or stale, none if a live process holds it
+    pub fn try_acquire(path: &Path) -> Result<Option<FileLock>, Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                    writeln!(
+                        file,
+                        "{}\n{}\n{}",
+                        process::id(),
+                        now.as_secs(),
+                        command_line()
+                    )?;
+                    log::debug!("acquired lock {}", path.display());
+                    return Ok(Some(FileLock {
+                        path: path.to_path_buf(),
+                    }));
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            let holder = read_holder(path);
+            if !is_stale(path, holder.as_ref()) {
+                return Ok(None);
+            }
+            log::warn!(
+                "remove stale lock {} of {:?}",
+                path.display(),
+                holder.as_ref().map(|h| h.pid)
+            );
+            // The lock might have been taken over by another process in the meantime
+            if read_holder(path) == holder {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+// The command line of this process with the program name only, e.g. "crane sync"
+fn command_line() -> String {
+    env::args()
+        .enumerate()
+        .map(|(i, arg)| match i {
+            0 => Path::new(&arg)
+                .file_name()
+                .map_or(arg.clone(), |name| name.to_string_lossy().to_string()),
+            _ => arg,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        log::debug!("release lock {}", self.path.display());
+        if let Err(err) = fs::remove_file(&self.path) {
+            log::warn!("failed to release lock {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+pub fn read_holder(path: &Path) -> Option<LockHolder> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut lines = content.lines();
+    let pid = lines.next()?.trim().parse().ok()?;
+    let since = lines.next()?.trim().parse().ok()?;
+    let command = lines
+        .next()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty());
+    Some(LockHolder {
+        pid,
+        since,
+        command,
+    })
+}
+
+pub fn is_stale(path: &Path, holder: Option<&LockHolder>) -> bool {
+    match holder {
+        Some(holder) => !process_alive(holder.pid),
+        None => fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > INCOMPLETE_LOCK_AGE),
+    }
+}
+
+pub fn process_alive(pid: u32) -> bool {
+    if pid == process::id() {
+        return true;
+    }
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else if cfg!(unix) {
+        process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(process::Stdio::null())
+            .status()
+            .map_or(true, |status| status.success())
+    } else {
+        // There is no cheap way to tell, so the lock is never considered stale
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn dead_pid() -> u32 {
+        let mut child = process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        pid
+    }
+
+    #[test]
+    fn test_file_lock_acquire_and_release() {
+        let temp_dir = TempDir::new("lock").expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("repo.lock");
+
+        let lock = FileLock::acquire(&path, "test", Duration::from_secs(1)).unwrap();
+        assert_eq!(read_holder(&path).unwrap().pid, process::id());
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_file_lock_times_out() {
+        let temp_dir = TempDir::new("lock").expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("repo.lock");
+
+        let _lock = FileLock::acquire(&path, "test", Duration::from_secs(1)).unwrap();
+        let err = FileLock::acquire(&path, "test", Duration::from_millis(300)).unwrap_err();
+        assert!(err
+            .message
+            .contains(&format!("held by pid {}", process::id())));
+    }
+
+    #[test]
+    fn test_file_lock_takes_over_stale_lock() {
+        let temp_dir = TempDir::new("lock").expect("Failed to create temporary directory");
+        let path = temp_dir.path().join("repo.lock");
+        fs::write(&path, format!("{}\n0\n", dead_pid())).unwrap();
+
+        let _lock = FileLock::acquire(&path, "test", Duration::from_secs(1)).unwrap();
+        assert_eq!(read_holder(&path).unwrap().pid, process::id());
+    }
+}
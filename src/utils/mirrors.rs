@@ -0,0 +1,149 @@
+use crate::utils::config::UserConfig;
+use crate::utils::git_url::GitUrl;
+use crate::utils::retry::is_transient;
+
+// Apply the url rewrites, the longest matching prefix wins like with insteadOf of git
+pub fn rewrite_url(config: &UserConfig, url: &str) -> String {
+    let rewrites = match &config.url_rewrites {
+        Some(rewrites) => rewrites,
+        None => return url.to_string(),
+    };
+    match rewrites
+        .0
+        .iter()
+        .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+    {
+        Some((prefix, replacement)) => format!("{}{}", replacement, &url[prefix.len()..]),
+        None => url.to_string(),
+    }
+}
+
+// Where `url` is fetched from in order: the mirrors of its host, then the rewritten url itself
+pub fn fetch_urls(config: &UserConfig, url: &str) -> Vec<String> {
+    let url = rewrite_url(config, url);
+    let mut urls = vec![];
+    if let (Some(mirrors), Ok(git_url)) = (&config.mirrors, GitUrl::parse(&url)) {
+        let host = git_url.host.unwrap_or_default().to_lowercase();
+        for (_, bases) in mirrors.0.iter().filter(|(h, _)| h.to_lowercase() == host) {
+            for base in bases {
+                urls.push(format!(
+                    "{}/{}",
+                    base.trim_end_matches('/'),
+                    git_url.path.trim_start_matches('/')
+                ));
+            }
+        }
+    }
+    urls.push(url);
+    urls
+}
+
+// Run `op` with each url `url` is fetched from until it succeeds or fails with an error other
+// than a connection failure, e.g. rejected credentials, the error of the last one is returned.
+// The url of a remote is never changed, mirrors only serve the objects.
+pub fn with_fallback<T>(
+    url: &str,
+    op: impl FnMut(&str) -> Result<T, git2::Error>,
+) -> Result<T, git2::Error> {
+    try_urls(url, &fetch_urls(&UserConfig::instance(), url), op)
+}
+
+fn try_urls<T>(
+    url: &str,
+    urls: &[String],
+    mut op: impl FnMut(&str) -> Result<T, git2::Error>,
+) -> Result<T, git2::Error> {
+    let mut last_error = None;
+    for fetch_url in urls {
+        if fetch_url != url {
+            log::info!("fetch {} from {}", url, fetch_url);
+        }
+        match op(fetch_url) {
+            Ok(result) => return Ok(result),
+            Err(e) if is_transient(&e) => {
+                log::warn!("failed to fetch from {}: {}", fetch_url, e);
+                last_error = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::rustpython::Dict;
+
+    #[test]
+    fn test_fetch_urls() {
+        let config = UserConfig {
+            url_rewrites: Some(Dict(vec![
+                (
+                    "https://github.com/".to_string(),
+                    "https://gh.example.com/".to_string(),
+                ),
+                (
+                    "https://github.com/org/".to_string(),
+                    "git@internal.example.com:org/".to_string(),
+                ),
+            ])),
+            mirrors: Some(Dict(vec![(
+                "GH.example.com".to_string(),
+                vec![
+                    "https://cn.example.com/gh/".to_string(),
+                    "https://eu.example.com/gh".to_string(),
+                ],
+            )])),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            rewrite_url(&config, "https://github.com/org/lib.git"),
+            "git@internal.example.com:org/lib.git"
+        );
+        assert_eq!(
+            fetch_urls(&config, "https://github.com/other/lib.git"),
+            vec![
+                "https://cn.example.com/gh/other/lib.git",
+                "https://eu.example.com/gh/other/lib.git",
+                "https://gh.example.com/other/lib.git",
+            ]
+        );
+        assert_eq!(
+            fetch_urls(&config, "https://gitlab.com/lib.git"),
+            vec!["https://gitlab.com/lib.git"]
+        );
+    }
+
+    #[test]
+    fn test_fallback_on_connection_failures_only() {
+        let url = "https://github.com/org/lib.git";
+        let urls = vec![
+            "https://eu.example.com/org/lib.git".to_string(),
+            "https://cn.example.com/org/lib.git".to_string(),
+            url.to_string(),
+        ];
+        let fail = |code| git2::Error::new(code, git2::ErrorClass::Net, "failed");
+
+        let mut tried = vec![];
+        let result = try_urls(url, &urls, |fetch_url| {
+            tried.push(fetch_url.to_string());
+            match tried.len() {
+                1 => Err(fail(git2::ErrorCode::GenericError)),
+                _ => Ok(fetch_url.to_string()),
+            }
+        });
+        assert_eq!(result.unwrap(), urls[1]);
+
+        // Rejected credentials are not tried with the next url
+        let mut tried = 0;
+        let result: Result<(), _> = try_urls(url, &urls, |_| {
+            tried += 1;
+            Err(fail(git2::ErrorCode::Auth))
+        });
+        assert_eq!(result.unwrap_err().code(), git2::ErrorCode::Auth);
+        assert_eq!(tried, 1);
+    }
+}
@@ -1,8 +1,18 @@
+pub mod alternates;
 pub mod cache;
+pub mod config;
+pub mod credentials;
 pub mod encode;
 pub mod fs;
+pub mod git_url;
 pub mod git_utils;
+pub mod interrupt;
+pub mod lock;
+pub mod mirrors;
 pub mod parser;
 pub mod process;
+pub mod retry;
 pub mod rustpython;
+pub mod selector;
 pub mod test_utils;
+pub mod workspace;
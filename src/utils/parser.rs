@@ -1,7 +1,7 @@
 use rustpython_vm::builtins::PyDict;
 use std::path::PathBuf;
 
-use crate::components::component::{Component, ComponentID};
+use crate::components::component::{Component, ComponentID, FromPyObject};
 use crate::errors::Error;
 use crate::utils::rustpython::format_py_exception;
 
@@ -47,3 +47,23 @@ pub fn parse_components<'a>(
         Ok(components)
     })
 }
+
+// load a python format file and build an object from the global variables defined in it
+pub fn parse_globals<T: FromPyObject>(config_file: &PathBuf) -> Result<T, Error> {
+    log::debug!("parsing globals defined in {:#?}", config_file);
+
+    let interp = rustpython::InterpreterConfig::new()
+        .init_stdlib()
+        .interpreter();
+
+    interp.enter(|vm| {
+        let scope = vm.new_scope_with_builtins();
+        vm.run_script(
+            scope.clone(),
+            config_file.clone().into_os_string().to_str().unwrap(),
+        )
+        .map_err(|err| Error::new(format_py_exception(&err, vm)))?;
+
+        T::from_py(&scope.globals.into(), vm)
+    })
+}
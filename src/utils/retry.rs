@@ -0,0 +1,164 @@
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use git2::{ErrorClass, ErrorCode};
+
+use crate::utils::config::UserConfig;
+
+const DEFAULT_FETCH_RETRIES: u32 = 3;
+const DEFAULT_FETCH_RETRY_DELAY: u64 = 1;
+const DEFAULT_CONNECT_TIMEOUT: u64 = 30;
+const DEFAULT_STALL_TIMEOUT: u64 = 60;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// How fetches are retried and when they are given up
+#[derive(Debug, Clone, Copy)]
+pub struct FetchPolicy {
+    // Attempts after the first one
+    pub retries: u32,
+    // Delay before the first retry, doubled for every further one
+    pub retry_delay: Duration,
+    // Time to get a first response from the remote
+    pub connect_timeout: Duration,
+    // Time without receiving anything once connected
+    pub stall_timeout: Duration,
+}
+
+impl FetchPolicy {
+    pub fn from_config(config: &UserConfig) -> Self {
+        FetchPolicy {
+            retries: config.fetch_retries.unwrap_or(DEFAULT_FETCH_RETRIES),
+            retry_delay: Duration::from_secs(
+                config
+                    .fetch_retry_delay
+                    .unwrap_or(DEFAULT_FETCH_RETRY_DELAY),
+            ),
+            connect_timeout: Duration::from_secs(
+                config.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            ),
+            stall_timeout: Duration::from_secs(
+                config.stall_timeout.unwrap_or(DEFAULT_STALL_TIMEOUT),
+            ),
+        }
+    }
+
+    // Exponential backoff with up to 50% of jitter, so that parallel fetches of the same
+    // host do not retry in lockstep
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let delay = self
+            .retry_delay
+            .saturating_mul(1 << retry.min(16))
+            .min(MAX_RETRY_DELAY);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        delay + delay.mul_f64(f64::from(nanos % 500) / 1000.0)
+    }
+}
+
+// Network failures might go away, a missing revision or rejected credentials do not
+pub fn is_transient(e: &git2::Error) -> bool {
+    matches!(
+        e.class(),
+        ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssh | ErrorClass::Os
+    ) && e.code() != ErrorCode::Auth
+        && e.code() != ErrorCode::Certificate
+}
+
+// Run `op` until it succeeds, fails for good or runs out of retries. `description` names the
+// operation in the log.
+pub fn retry<T>(
+    policy: &FetchPolicy,
+    description: &str,
+    mut op: impl FnMut() -> Result<T, git2::Error>,
+) -> Result<T, git2::Error> {
+    let mut retry = 0;
+    loop {
+        log::debug!(
+            "{}, attempt {}/{}",
+            description,
+            retry + 1,
+            policy.retries + 1
+        );
+        match op() {
+            Ok(result) => return Ok(result),
+            Err(e) if retry < policy.retries && is_transient(&e) => {
+                let delay = policy.backoff(retry);
+                log::warn!(
+                    "{} failed, attempt {}/{}: {}, retry in {:.1}s",
+                    description,
+                    retry + 1,
+                    policy.retries + 1,
+                    e.message(),
+                    delay.as_secs_f64()
+                );
+                thread::sleep(delay);
+                retry += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(retries: u32) -> FetchPolicy {
+        FetchPolicy {
+            retries,
+            retry_delay: Duration::ZERO,
+            connect_timeout: Duration::from_secs(1),
+            stall_timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = FetchPolicy {
+            retry_delay: Duration::from_secs(1),
+            ..policy(3)
+        };
+        for (retry, delay) in [(0, 1), (1, 2), (2, 4), (10, 60)] {
+            let backoff = policy.backoff(retry);
+            assert!(backoff >= Duration::from_secs(delay));
+            assert!(backoff <= Duration::from_secs(delay).mul_f64(1.5));
+        }
+    }
+
+    #[test]
+    fn test_retry() {
+        let network = || git2::Error::new(ErrorCode::GenericError, ErrorClass::Net, "reset");
+        let mut attempts = 0;
+        let result = retry(&policy(3), "test", || {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(network()),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        attempts = 0;
+        let result: Result<(), _> = retry(&policy(2), "test", || {
+            attempts += 1;
+            Err(network())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        // Not worth retrying
+        attempts = 0;
+        let result: Result<(), _> = retry(&policy(2), "test", || {
+            attempts += 1;
+            Err(git2::Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Reference,
+                "no such ref",
+            ))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
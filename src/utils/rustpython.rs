@@ -1,4 +1,5 @@
-use rustpython_vm::{builtins::PyBaseException, PyRef, VirtualMachine};
+use rustpython_vm::builtins::{PyBaseException, PyDict};
+use rustpython_vm::{PyObjectRef, PyRef, PyResult, TryFromObject, VirtualMachine};
 
 pub fn format_py_exception(exc: &PyRef<PyBaseException>, vm: &VirtualMachine) -> String {
     let mut msg = String::new();
@@ -7,3 +8,26 @@ pub fn format_py_exception(exc: &PyRef<PyBaseException>, vm: &VirtualMachine) ->
     }
     return msg;
 }
+
+// A python dict with string keys, items are kept in the order they are declared
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dict<T>(pub Vec<(String, T)>);
+
+impl<T> Default for Dict<T> {
+    fn default() -> Self {
+        Dict(vec![])
+    }
+}
+
+impl<T: TryFromObject> TryFromObject for Dict<T> {
+    fn try_from_object(vm: &VirtualMachine, obj: PyObjectRef) -> PyResult<Self> {
+        let dict = obj
+            .downcast::<PyDict>()
+            .map_err(|_| vm.new_type_error(String::from("expected a dict")))?;
+        let mut items = vec![];
+        for (key, value) in dict {
+            items.push((key.try_into_value(vm)?, value.try_into_value(vm)?));
+        }
+        Ok(Dict(items))
+    }
+}
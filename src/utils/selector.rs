@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+
+// Components picked on the command line by name, path prefix or glob. A component is selected
+// if it matches a pattern or is nested in a selected component. Paths are relative to the root
+// of the solution.
+#[derive(Debug)]
+pub struct Selection {
+    root_dir: PathBuf,
+    patterns: Vec<String>,
+    // Directories of the components matching a pattern, and the patterns which matched
+    selected: Mutex<(Vec<PathBuf>, Vec<String>)>,
+}
+
+impl Selection {
+    pub fn new(root_dir: &Path, patterns: &[String]) -> Self {
+        Selection {
+            root_dir: root_dir.to_path_buf(),
+            patterns: patterns.iter().map(|p| normalize(p)).collect(),
+            selected: Mutex::new((vec![], vec![])),
+        }
+    }
+
+    // Parents are visited before their nested deps, which are in the directory of the parent
+    pub fn includes(&self, name: &str, target_dir: &Path) -> bool {
+        let path = target_dir
+            .strip_prefix(&self.root_dir)
+            .unwrap_or(target_dir);
+        let mut selected = self.selected.lock();
+        if selected.0.iter().any(|dir| path.starts_with(dir)) {
+            return true;
+        }
+
+        let path_str = path.to_string_lossy().replace('\\', "/");
+        let matched: Vec<String> = self
+            .patterns
+            .iter()
+            .filter(|pattern| matches(pattern, name, &path_str))
+            .cloned()
+            .collect();
+        if matched.is_empty() {
+            return false;
+        }
+        selected.0.push(path.to_path_buf());
+        selected.1.extend(matched);
+        true
+    }
+
+    // Patterns which did not match any component
+    pub fn unmatched(&self) -> Vec<String> {
+        let selected = self.selected.lock();
+        self.patterns
+            .iter()
+            .filter(|pattern| !selected.1.contains(pattern))
+            .cloned()
+            .collect()
+    }
+}
+
+fn normalize(pattern: &str) -> String {
+    let pattern = pattern.replace('\\', "/");
+    let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+    match pattern {
+        "." => String::new(),
+        _ => pattern.to_string(),
+    }
+}
+
+fn matches(pattern: &str, name: &str, path: &str) -> bool {
+    if pattern.is_empty() || pattern == name || pattern == path {
+        return true;
+    }
+    if path.starts_with(pattern) && path[pattern.len()..].starts_with('/') {
+        return true;
+    }
+    pattern.contains(['*', '?']) && (glob_match(pattern, name) || glob_match(pattern, path))
+}
+
+// "*" matches within a path segment, "**" across segments and "?" any single character
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match_chars(&pattern, &text)
+}
+
+fn glob_match_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            let rest = rest.strip_prefix(&['/']).unwrap_or(rest);
+            (0..=text.len()).any(|i| glob_match_chars(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => {
+            let segment = text.iter().position(|c| *c == '/').unwrap_or(text.len());
+            (0..=segment).any(|i| glob_match_chars(rest, &text[i..]))
+        }
+        ['?', rest @ ..] => {
+            matches!(text.first(), Some(c) if *c != '/') && glob_match_chars(rest, &text[1..])
+        }
+        [c, rest @ ..] => text.first() == Some(c) && glob_match_chars(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("tools/*", "tools/clang"));
+        assert!(!glob_match("tools/*", "tools/clang/extra"));
+        assert!(glob_match("tools/**", "tools/clang/extra"));
+        assert!(glob_match("**/llvm", "third_party/llvm"));
+        assert!(glob_match("**/llvm", "llvm"));
+        assert!(glob_match("lib?", "lib1"));
+        assert!(!glob_match("lib?", "lib"));
+        assert!(!glob_match("*", "a/b"));
+    }
+
+    #[test]
+    fn test_selection() {
+        let root = PathBuf::from("/ws");
+        let selection = Selection::new(
+            &root,
+            &[
+                "third_party/llvm/".to_string(),
+                "tools/*".to_string(),
+                "zlib".to_string(),
+                "missing".to_string(),
+            ],
+        );
+
+        assert!(!selection.includes("(main)", &root));
+        assert!(selection.includes("llvm", &root.join("third_party/llvm")));
+        // Nested deps of a selected component
+        assert!(selection.includes("lit", &root.join("third_party/llvm/utils/lit")));
+        assert!(!selection.includes("boost", &root.join("third_party/boost")));
+        assert!(selection.includes("clang", &root.join("tools/clang")));
+        assert!(selection.includes("zlib", &root.join("deps/z")));
+        assert!(selection.includes("minizip", &root.join("deps/z/contrib")));
+        assert_eq!(selection.unmatched(), vec!["missing".to_string()]);
+    }
+}
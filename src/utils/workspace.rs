@@ -0,0 +1,473 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::WORKSPACE_DIR;
+use crate::errors::Error;
+use crate::utils::git_url::GitUrl;
+use crate::utils::git_utils::get_git_dir_path;
+use crate::utils::lock::{is_stale, read_holder, FileLock, LockHolder, RETRY_INTERVAL};
+
+const STATE_FILE: &str = "state.json";
+const LOCK_FILE: &str = "lock";
+// A file per process holding a shared lock, named by its pid
+const READERS_DIR: &str = "readers";
+
+// Set by --wait, otherwise a command fails right away when the workspace is locked
+static WAIT_FOR_LOCK: AtomicBool = AtomicBool::new(false);
+
+// What the last sync left in a workspace: the paths of the components of each url, relative
+// to the root of the solution. It is kept in <root>/.crane_workspace/state.json.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WorkspaceState {
+    #[serde(skip)]
+    root_dir: PathBuf,
+    components: BTreeMap<String, Vec<PathBuf>>,
+    // Components synced by the running sync, they replace the entries of their urls once every
+    // component was walked
+    #[serde(skip)]
+    synced: Mutex<BTreeMap<String, Vec<PathBuf>>>,
+}
+
+impl WorkspaceState {
+    pub fn state_file(root_dir: &Path) -> PathBuf {
+        root_dir.join(WORKSPACE_DIR).join(STATE_FILE)
+    }
+
+    pub fn load(root_dir: &Path) -> Result<Self, Error> {
+        let file = Self::state_file(root_dir);
+        let mut state: WorkspaceState = if file.exists() {
+            serde_json::from_str(&fs::read_to_string(&file)?)?
+        } else {
+            WorkspaceState::default()
+        };
+        state.root_dir = root_dir.to_path_buf();
+        Ok(state)
+    }
+
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
+
+    // Components recorded since loading the state, paths are absolute
+    pub fn synced(&self) -> Vec<(String, PathBuf)> {
+        self.synced
+            .lock()
+            .iter()
+            .flat_map(|(url, paths)| {
+                paths
+                    .iter()
+                    .map(move |path| (url.clone(), self.root_dir.join(path)))
+            })
+            .collect()
+    }
+
+    // Where the only component of `url` was, none if there were none or several
+    pub fn previous_path(&self, url: &str) -> Result<Option<PathBuf>, Error> {
+        let key = GitUrl::parse(url)?.canonical();
+        Ok(
+            match self.components.get(&key).map(|paths| paths.as_slice()) {
+                Some([path]) => Some(self.root_dir.join(path)),
+                _ => None,
+            },
+        )
+    }
+
+    // Every component and its url, paths are absolute
+    pub fn components(&self) -> Vec<(String, PathBuf)> {
+        self.components
+            .iter()
+            .flat_map(|(url, paths)| {
+                paths
+                    .iter()
+                    .map(move |path| (url.clone(), self.root_dir.join(path)))
+            })
+            .collect()
+    }
+
+    // Components of the last sync which the running sync did not visit, it must have walked
+    // every component
+    pub fn leftovers(&self) -> Vec<(String, PathBuf)> {
+        let synced = self.synced.lock();
+        self.components()
+            .into_iter()
+            .filter(|(_, path)| {
+                let path = path.strip_prefix(&self.root_dir).unwrap_or(path);
+                !synced.values().flatten().any(|p| p == path)
+            })
+            .collect()
+    }
+
+    // Drop a component which is not in the workspace any more
+    pub fn forget(&mut self, target_dir: &Path) {
+        let path = target_dir
+            .strip_prefix(&self.root_dir)
+            .unwrap_or(target_dir);
+        for paths in self.components.values_mut() {
+            paths.retain(|p| p != path);
+        }
+        self.components.retain(|_, paths| !paths.is_empty());
+    }
+
+    pub fn record(&self, url: &str, target_dir: &Path) -> Result<(), Error> {
+        let key = GitUrl::parse(url)?.canonical();
+        let path = target_dir
+            .strip_prefix(&self.root_dir)
+            .unwrap_or(target_dir)
+            .to_path_buf();
+        let mut synced = self.synced.lock();
+        let paths = synced.entry(key).or_default();
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+        Ok(())
+    }
+
+    // Write the state with the components synced since loading it, the entries of other urls
+    // are kept. Unless every component was walked, components might have been left out by a
+    // selection or a failure, so the synced paths of a url are added to the ones it had, of
+    // which only those gone from the workspace are dropped.
+    pub fn save(&mut self, walked_all: bool) -> Result<(), Error> {
+        let synced = std::mem::take(&mut *self.synced.lock());
+        for (url, synced_paths) in synced {
+            let mut paths = match self.components.remove(&url) {
+                Some(paths) if !walked_all => paths
+                    .into_iter()
+                    .filter(|path| self.root_dir.join(path).exists())
+                    .collect(),
+                _ => vec![],
+            };
+            for path in synced_paths {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+            paths.sort();
+            self.components.insert(url, paths);
+        }
+
+        // The solution could not be cloned
+        if !self.root_dir.exists() {
+            return Ok(());
+        }
+        ensure_workspace_dir(&self.root_dir)?;
+        let file = Self::state_file(&self.root_dir);
+        let temp_file = file.with_extension("tmp");
+        fs::write(&temp_file, serde_json::to_string_pretty(&self)?)?;
+        fs::rename(temp_file, file)?;
+        Ok(())
+    }
+}
+
+pub fn set_wait_for_lock(wait: bool) {
+    WAIT_FOR_LOCK.store(wait, Ordering::SeqCst);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    // Commands which only read the workspace run along with each other
+    Shared,
+    // Commands which change the workspace run alone
+    Exclusive,
+}
+
+// Keeps crane processes from racing on the repositories of a workspace. An exclusive lock is
+// <root>/.crane_workspace/lock, a shared lock is a file in <root>/.crane_workspace/readers
+// which is only created while holding the exclusive lock. The lock is released when the value
+// is dropped.
+#[derive(Debug)]
+pub struct WorkspaceLock {
+    _lock: FileLock,
+}
+
+impl WorkspaceLock {
+    // None if there is no workspace in `root_dir` yet. Readers do not create the workspace
+    // directory, so none either for a shared lock of a workspace without one, which no crane
+    // process changing it can be running in.
+    pub fn acquire(root_dir: &Path, mode: LockMode) -> Result<Option<WorkspaceLock>, Error> {
+        if !root_dir.is_dir() {
+            return Ok(None);
+        }
+        let dir = match mode {
+            LockMode::Exclusive => ensure_workspace_dir(root_dir)?,
+            LockMode::Shared if root_dir.join(WORKSPACE_DIR).is_dir() => {
+                root_dir.join(WORKSPACE_DIR)
+            }
+            LockMode::Shared => return Ok(None),
+        };
+        let lock_file = dir.join(LOCK_FILE);
+        let readers_dir = dir.join(READERS_DIR);
+
+        let lock = wait_for_lock(|| {
+            Ok(FileLock::try_acquire(&lock_file)?.ok_or_else(|| read_holder(&lock_file)))
+        })?;
+        let lock = match mode {
+            LockMode::Shared => {
+                let reader = readers_dir.join(process::id().to_string());
+                // Left by a dead process whose pid is reused
+                let _ = fs::remove_file(&reader);
+                FileLock::try_acquire(&reader)?
+                    .ok_or_else(|| Error::new(format!("{} is locked already", reader.display())))?
+            }
+            // New readers wait for the lock held meanwhile
+            LockMode::Exclusive => {
+                wait_for_lock(|| readers_gone(&readers_dir))?;
+                lock
+            }
+        };
+        Ok(Some(WorkspaceLock { _lock: lock }))
+    }
+}
+
+// Try `attempt` until it succeeds, or once without --wait. It returns the holder of the lock
+// when it fails.
+fn wait_for_lock<T>(
+    mut attempt: impl FnMut() -> Result<Result<T, Option<LockHolder>>, Error>,
+) -> Result<T, Error> {
+    let mut reported_holder = None;
+    loop {
+        let holder = match attempt()? {
+            Ok(value) => return Ok(value),
+            Err(holder) => holder,
+        };
+        let holder_name = holder
+            .as_ref()
+            .map_or(String::from("another crane process"), |h| h.to_string());
+        if !WAIT_FOR_LOCK.load(Ordering::SeqCst) {
+            return Err(Error::new(format!(
+                "The workspace is in use by {}, pass --wait to wait for it",
+                holder_name
+            )));
+        }
+        let pid = holder.map(|h| h.pid);
+        if reported_holder != Some(pid) {
+            println!("Waiting for the workspace in use by {}", holder_name);
+            reported_holder = Some(pid);
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+// Fails with a process still holding a shared lock, stale locks are removed
+fn readers_gone(readers_dir: &Path) -> Result<Result<(), Option<LockHolder>>, Error> {
+    if !readers_dir.exists() {
+        return Ok(Ok(()));
+    }
+    for entry in fs::read_dir(readers_dir)? {
+        let path = entry?.path();
+        let holder = read_holder(&path);
+        if !is_stale(&path, holder.as_ref()) {
+            return Ok(Err(holder));
+        }
+        let _ = fs::remove_file(&path);
+    }
+    Ok(Ok(()))
+}
+
+// The directory of the workspace files, kept out of `git status` of the solution
+fn ensure_workspace_dir(root_dir: &Path) -> Result<PathBuf, Error> {
+    let dir = root_dir.join(WORKSPACE_DIR);
+    fs::create_dir_all(&dir)?;
+    // The solution might have been cloned since the directory was created
+    exclude_from_git(root_dir)?;
+    Ok(dir)
+}
+
+fn exclude_from_git(root_dir: &Path) -> Result<(), Error> {
+    let git_dir = match get_git_dir_path(root_dir) {
+        Ok(git_dir) => git_dir,
+        Err(_) => return Ok(()),
+    };
+    let exclude_file = git_dir.join("info").join("exclude");
+    let content = fs::read_to_string(&exclude_file).unwrap_or_default();
+    let pattern = format!("/{}/", WORKSPACE_DIR);
+    if content.lines().any(|line| line.trim() == pattern) {
+        return Ok(());
+    }
+    fs::create_dir_all(exclude_file.parent().unwrap())?;
+    let separator = if content.is_empty() || content.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    fs::write(
+        exclude_file,
+        format!("{}{}{}\n", content, separator, pattern),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Repository;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_save_and_load() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        Repository::init(root).unwrap();
+
+        let mut state = WorkspaceState::load(root).unwrap();
+        state
+            .record("https://github.com/org/lib.git", &root.join("lib"))
+            .unwrap();
+        state
+            .record("https://github.com/org/tool.git", &root.join("tools/a"))
+            .unwrap();
+        state
+            .record("https://github.com/org/tool.git", &root.join("tools/b"))
+            .unwrap();
+        state.save(true).unwrap();
+
+        let mut state = WorkspaceState::load(root).unwrap();
+        assert_eq!(
+            state
+                .previous_path("https://GitHub.com/org/lib.git")
+                .unwrap(),
+            Some(root.join("lib"))
+        );
+        // Ambiguous
+        assert_eq!(
+            state
+                .previous_path("https://github.com/org/tool.git")
+                .unwrap(),
+            None
+        );
+
+        // Only the urls synced again are replaced
+        state
+            .record(
+                "https://github.com/org/lib.git",
+                &root.join("third_party/lib"),
+            )
+            .unwrap();
+        state.save(true).unwrap();
+        let state = WorkspaceState::load(root).unwrap();
+        assert_eq!(
+            state
+                .previous_path("https://github.com/org/lib.git")
+                .unwrap(),
+            Some(root.join("third_party/lib"))
+        );
+        assert_eq!(state.components().len(), 3);
+
+        // Only the lib was synced again
+        let mut state = WorkspaceState::load(root).unwrap();
+        state
+            .record(
+                "https://github.com/org/lib.git",
+                &root.join("third_party/lib"),
+            )
+            .unwrap();
+        let leftovers = state.leftovers();
+        assert_eq!(leftovers.len(), 2);
+        for (_, path) in leftovers {
+            state.forget(&path);
+        }
+        state.save(true).unwrap();
+        let state = WorkspaceState::load(root).unwrap();
+        assert_eq!(
+            state.components(),
+            vec![(
+                "https://github.com/org/lib.git".to_string(),
+                root.join("third_party/lib")
+            )]
+        );
+
+        let exclude = fs::read_to_string(root.join(".git/info/exclude")).unwrap();
+        assert!(exclude.contains("/.crane_workspace/"));
+    }
+
+    #[test]
+    fn test_save_after_selection() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        Repository::init(root).unwrap();
+        for dir in ["tools/a", "tools/b"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let url = "https://github.com/org/tool.git";
+
+        let mut state = WorkspaceState::load(root).unwrap();
+        state.record(url, &root.join("tools/a")).unwrap();
+        state.record(url, &root.join("tools/b")).unwrap();
+        state.record(url, &root.join("tools/c")).unwrap();
+        state.save(true).unwrap();
+
+        // Only a is selected, b is kept and c is gone
+        let mut state = WorkspaceState::load(root).unwrap();
+        state.record(url, &root.join("tools/a")).unwrap();
+        state.save(false).unwrap();
+        let mut state = WorkspaceState::load(root).unwrap();
+        assert_eq!(
+            state.components(),
+            vec![
+                (url.to_string(), root.join("tools/a")),
+                (url.to_string(), root.join("tools/b"))
+            ]
+        );
+
+        // Every component was walked, b is not declared any more
+        state.record(url, &root.join("tools/a")).unwrap();
+        state.save(true).unwrap();
+        let state = WorkspaceState::load(root).unwrap();
+        assert_eq!(
+            state.components(),
+            vec![(url.to_string(), root.join("tools/a"))]
+        );
+    }
+
+    #[test]
+    fn test_workspace_lock() {
+        let temp_dir = TempDir::new("test_repo").expect("Failed to create temporary directory");
+        let root = temp_dir.path();
+        Repository::init(root).unwrap();
+        let in_use = format!("in use by pid {}", process::id());
+
+        // Readers leave a workspace without its directory alone
+        let exclude_file = root.join(".git/info/exclude");
+        let exclude = fs::read_to_string(&exclude_file).unwrap_or_default();
+        assert!(WorkspaceLock::acquire(root, LockMode::Shared)
+            .unwrap()
+            .is_none());
+        assert!(!root.join(WORKSPACE_DIR).exists());
+        assert_eq!(
+            fs::read_to_string(&exclude_file).unwrap_or_default(),
+            exclude
+        );
+
+        let lock = WorkspaceLock::acquire(root, LockMode::Exclusive)
+            .unwrap()
+            .unwrap();
+        let err = WorkspaceLock::acquire(root, LockMode::Shared).unwrap_err();
+        assert!(err.message.contains(&in_use), "{}", err.message);
+        drop(lock);
+
+        let shared = WorkspaceLock::acquire(root, LockMode::Shared)
+            .unwrap()
+            .unwrap();
+        let err = WorkspaceLock::acquire(root, LockMode::Exclusive).unwrap_err();
+        assert!(err.message.contains(&in_use), "{}", err.message);
+        drop(shared);
+        WorkspaceLock::acquire(root, LockMode::Exclusive)
+            .unwrap()
+            .unwrap();
+
+        // Nothing to lock before the solution is cloned
+        assert!(
+            WorkspaceLock::acquire(&root.join("missing"), LockMode::Exclusive)
+                .unwrap()
+                .is_none()
+        );
+        let exclude = fs::read_to_string(root.join(".git/info/exclude")).unwrap();
+        assert!(exclude.contains("/.crane_workspace/"));
+    }
+}
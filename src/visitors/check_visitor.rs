@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::components::component::{ComponentArena, ComponentID};
+use crate::components::git_dependency::GitDependency;
+use crate::errors::Error;
+use crate::visitors::component_visitor::ComponentVisitor;
+use crate::visitors::sync_visitor::{fetch_into_cache, verify_pinned_commit};
+use async_trait::async_trait;
+use colored::Colorize;
+use git2::{Oid, Repository};
+
+#[derive(Copy, Clone)]
+pub struct CheckVisitor<'a> {
+    // Only warn about pinned commits which are not on their branch
+    allow_unreachable: bool,
+    // Number of components which failed the check
+    failures: &'a AtomicUsize,
+}
+
+impl<'a> CheckVisitor<'a> {
+    pub fn new(allow_unreachable: bool, failures: &'a AtomicUsize) -> Self {
+        CheckVisitor {
+            allow_unreachable,
+            failures,
+        }
+    }
+
+    // Fetch into the global cache only, the workspace is left untouched.
+    // Returns a warning if the pinned commit is not on its branch but that is allowed.
+    fn check(&self, git: &GitDependency) -> Result<Option<String>, Error> {
+        let url = &git.url;
+        match (&git.branch, &git.commit) {
+            (Some(branch), Some(commit)) => {
+                let (cache_dir, tip) = fetch_into_cache(&[branch], url, None)?;
+                let commit = Oid::from_str(commit)?;
+                let cache_repo = Repository::open(&cache_dir)?;
+                match verify_pinned_commit(&cache_repo, branch, commit, tip) {
+                    Ok(()) => Ok(None),
+                    Err(e) if self.allow_unreachable => {
+                        if cache_repo.find_commit(commit).is_err() {
+                            fetch_into_cache(&[&commit.to_string()], url, None)?;
+                        }
+                        Ok(Some(e.message))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            (Some(branch), None) => {
+                fetch_into_cache(&[branch], url, None)?;
+                Ok(None)
+            }
+            (None, Some(commit)) => {
+                fetch_into_cache(&[commit], url, None)?;
+                Ok(None)
+            }
+            (None, None) => Err(Error::new(String::from(
+                "neither branch nor commit is specified",
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl ComponentVisitor for CheckVisitor<'_> {
+    async fn visit_solution(&self, id: ComponentID, root_dir: &PathBuf) -> Result<(), Error> {
+        self.visit_git(id, root_dir).await
+    }
+
+    async fn visit_git(&self, id: ComponentID, _root_dir: &PathBuf) -> Result<(), Error> {
+        let comp = ComponentArena::instance().get(id).unwrap();
+        let git = comp.impl_.as_any().downcast_ref::<GitDependency>().unwrap();
+        log::debug!("check git component: {}", comp.name);
+
+        match self.check(git) {
+            Ok(None) => println!("{}: {}", comp.name, "ok".green()),
+            Ok(Some(warning)) => println!("{}: {} {}", comp.name, "warning:".yellow(), warning),
+            Err(e) => {
+                println!("{}: {}", comp.name, e.message.red());
+                self.failures.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        Ok(())
+    }
+}
@@ -49,19 +49,11 @@ pub trait ComponentVisitor: std::marker::Copy + std::marker::Sync {
         if let Some(deps_file) = &deps_file {
             log::debug!("visit deps of solution {} in {}", id, deps_file);
             let deps_file_path = root_dir.join(PathBuf::from(deps_file));
-            let children = walk_components(self, &root_dir, &deps_file_path).await?;
+            let mut children = walk_components(self, id, root_dir, &deps_file_path).await?;
 
             let arena = ComponentArena::instance();
-            {
-                let mut comp = arena.get_mut(id).unwrap();
-                comp.add_children(&mut children.clone());
-            }
-            {
-                for child_id in children {
-                    let mut child = arena.get_mut(child_id).unwrap();
-                    child.parent_id = Some(id);
-                }
-            }
+            let mut comp = arena.get_mut(id).unwrap();
+            comp.add_children(&mut children);
         }
         Ok(())
     }
@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+use crate::components::component::{ComponentArena, ComponentID};
+use crate::errors::Error;
+use crate::utils::git_utils;
+use crate::visitors::component_visitor::ComponentVisitor;
+use async_trait::async_trait;
+
+#[derive(Copy, Clone, Default)]
+pub struct DissociateVisitor {}
+
+impl DissociateVisitor {
+    pub fn new() -> Self {
+        DissociateVisitor {}
+    }
+}
+
+#[async_trait]
+impl ComponentVisitor for DissociateVisitor {
+    async fn visit_solution(&self, id: ComponentID, root_dir: &PathBuf) -> Result<(), Error> {
+        self.visit_git(id, root_dir).await
+    }
+
+    async fn visit_git(&self, id: ComponentID, root_dir: &PathBuf) -> Result<(), Error> {
+        let name;
+        let target_dir;
+        {
+            let comp = ComponentArena::instance().get(id).unwrap();
+            name = comp.name.clone();
+            target_dir = root_dir.clone().join(&comp.target_dir);
+        }
+        log::debug!("dissociate git component: {} in {:?}", name, target_dir);
+        if git_utils::dissociate(&target_dir)? {
+            println!("Dissociated {} from the global cache", name);
+        }
+        Ok(())
+    }
+}
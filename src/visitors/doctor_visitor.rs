@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+
+use crate::components::component::{ComponentArena, ComponentID};
+use crate::errors::Error;
+use crate::utils::alternates;
+use crate::utils::cache::get_cache_dir;
+use crate::visitors::component_visitor::ComponentVisitor;
+use async_trait::async_trait;
+use colored::Colorize;
+
+#[derive(Copy, Clone, Default)]
+pub struct DoctorVisitor {
+    // Deduplicate the alternates and re-point the missing ones to the current cache dir
+    fix: bool,
+}
+
+impl DoctorVisitor {
+    pub fn new(fix: bool) -> Self {
+        DoctorVisitor { fix }
+    }
+
+    fn check_alternates(&self, target_dir: &Path) -> Result<Vec<String>, Error> {
+        let mut lines = vec![];
+        if self.fix {
+            let cache_git_dir = get_cache_dir()?.join("git");
+            for (old, new) in alternates::repoint(target_dir, &cache_git_dir)? {
+                lines.push(format!(
+                    "{} {} -> {}",
+                    "re-pointed".green(),
+                    old.display(),
+                    new.display()
+                ));
+            }
+            let entries = alternates::read(target_dir)?;
+            alternates::write(target_dir, &entries)?;
+        }
+
+        let entries = alternates::read(target_dir)?;
+        if entries.is_empty() {
+            lines.push(String::from("no alternates"));
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            let state = if entries[..i].contains(entry) {
+                "duplicate".yellow()
+            } else if entry.is_dir() {
+                "ok".green()
+            } else {
+                "missing".red()
+            };
+            lines.push(format!("{} {}", state, entry.display()));
+        }
+        Ok(lines)
+    }
+}
+
+#[async_trait]
+impl ComponentVisitor for DoctorVisitor {
+    async fn visit_solution(&self, id: ComponentID, root_dir: &PathBuf) -> Result<(), Error> {
+        self.visit_git(id, root_dir).await
+    }
+
+    async fn visit_git(&self, id: ComponentID, root_dir: &PathBuf) -> Result<(), Error> {
+        let name;
+        let target_dir;
+        {
+            let comp = ComponentArena::instance().get(id).unwrap();
+            name = comp.name.clone();
+            target_dir = root_dir.clone().join(&comp.target_dir);
+        }
+        log::debug!("check git component: {} in {:?}", name, target_dir);
+
+        // Print the whole report at once, components are checked concurrently
+        let mut report = format!("{} ({})\n", name, target_dir.display());
+        for line in self.check_alternates(&target_dir)? {
+            report.push_str(&format!("    {}\n", line));
+        }
+        print!("{}", report);
+        Ok(())
+    }
+}
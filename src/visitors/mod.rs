@@ -1,3 +1,7 @@
+pub mod check_visitor;
 pub mod component_visitor;
+pub mod dissociate_visitor;
+pub mod doctor_visitor;
+pub mod prune_visitor;
 pub mod status_visitor;
 pub mod sync_visitor;
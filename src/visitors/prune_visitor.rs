@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+use crate::components::component::{ComponentArena, ComponentID};
+use crate::components::git_dependency::GitDependency;
+use crate::errors::Error;
+use crate::utils::workspace::WorkspaceState;
+use crate::visitors::component_visitor::ComponentVisitor;
+use async_trait::async_trait;
+
+// Record the components declared in the configuration, whatever is in the workspace state
+// and not declared any more is left over
+#[derive(Copy, Clone)]
+pub struct PruneVisitor<'a> {
+    state: &'a WorkspaceState,
+}
+
+impl<'a> PruneVisitor<'a> {
+    pub fn new(state: &'a WorkspaceState) -> Self {
+        PruneVisitor { state }
+    }
+}
+
+#[async_trait]
+impl ComponentVisitor for PruneVisitor<'_> {
+    async fn visit_solution(&self, id: ComponentID, root_dir: &PathBuf) -> Result<(), Error> {
+        self.visit_git(id, root_dir).await
+    }
+
+    async fn visit_git(&self, id: ComponentID, root_dir: &PathBuf) -> Result<(), Error> {
+        let comp = ComponentArena::instance().get(id).unwrap();
+        let git = comp.impl_.as_any().downcast_ref::<GitDependency>().unwrap();
+        let target_dir = root_dir.clone().join(&comp.target_dir);
+        // The solution itself is never pruned
+        if target_dir == self.state.root_dir() {
+            return Ok(());
+        }
+        log::debug!("{} is declared in {}", comp.name, target_dir.display());
+        self.state.record(&git.url, &target_dir)
+    }
+}
@@ -1,49 +1,1009 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use crate::components::component::{ComponentArena, ComponentID};
 use crate::components::git_dependency::GitDependency;
+use crate::constants::CACHE_PINS_REF_PREFIX;
 use crate::errors::Error;
-use crate::utils::git_utils::{self, add_alternate, fetch_repository, open_or_create_repo};
-use crate::utils::{cache::ensure_cache_dir, encode::string_to_base64};
+use crate::utils::alternates;
+use crate::utils::cache::{
+    cache_repo_dir, ensure_git_cache_dir, lock_cache_repo, mark_registered, register_workspace_repo,
+};
+use crate::utils::git_url::GitUrl;
+use crate::utils::git_utils::{
+    self, fetch_repository, open_or_create_repo, RebaseResult, UnstashResult,
+};
+use crate::utils::interrupt;
+use crate::utils::selector::Selection;
+use crate::utils::workspace::WorkspaceState;
 use crate::visitors::component_visitor::ComponentVisitor;
 use async_trait::async_trait;
-use git2::{AnnotatedCommit, Oid, Repository};
+use clap::ValueEnum;
+use colored::Colorize;
+use git2::{Oid, Repository};
+use parking_lot::Mutex;
+use serde::Serialize;
 
-pub fn fetch_with_alternate<'a>(
-    repo: &'a Repository,
+// Fetch `refs` into the cache repository of `remote_url`, returns the cache repository
+// directory and the fetched commit
+pub fn fetch_into_cache(
     refs: &[&str],
-    remote_url: &'a str,
+    remote_url: &str,
     remote_name: Option<&str>,
-) -> Result<AnnotatedCommit<'a>, Error> {
+) -> Result<(PathBuf, Oid), Error> {
+    let cache_dir = ensure_git_cache_dir()?.join(GitUrl::parse(remote_url)?.cache_key());
+    log::debug!("create cache repository");
+    // Crane processes syncing other workspaces might fetch into the same cache repository
+    let _cache_lock = lock_cache_repo(&cache_dir, remote_url)?;
+    let created = !cache_dir.exists();
+    let cache_repo = open_or_create_repo(&cache_dir)?;
+    if created {
+        mark_registered(&cache_repo)?;
+    }
+    let fetch_head = fetch_repository(&cache_repo, remote_url, refs, remote_name)?.id();
+    if let [branch] = refs {
+        if Oid::from_str(branch).is_err() || branch.len() != 40 {
+            record_cache_tip(&cache_repo, remote_name, branch, fetch_head)?;
+        }
+    }
+    Ok((cache_dir, fetch_head))
+}
+
+// Keep the tip of a branch in the cache repository, it is where an offline sync finds it
+fn record_cache_tip(
+    cache_repo: &Repository,
+    remote_name: Option<&str>,
+    branch: &str,
+    tip: Oid,
+) -> Result<(), Error> {
+    let refname = format!(
+        "refs/remotes/{}/{}",
+        remote_name.unwrap_or("origin"),
+        branch
+    );
+    cache_repo.reference(
+        &refname,
+        tip,
+        true,
+        &format!("Update {} to {}", branch, tip),
+    )?;
+    Ok(())
+}
+
+// Keep a pinned commit with a ref, workspaces borrow it from the cache repository without a
+// ref of their own and `git gc` would drop it otherwise. A pinned commit counts as pushed.
+fn record_pin(repo: &Repository, commit: Oid) -> Result<(), Error> {
+    repo.reference(
+        &format!("{}{}", CACHE_PINS_REF_PREFIX, commit),
+        commit,
+        true,
+        &format!("Pin {}", commit),
+    )?;
+    Ok(())
+}
+
+// Fail unless `commit` is on `branch`, whose tip is `tip`
+pub fn verify_pinned_commit(
+    repo: &Repository,
+    branch: &str,
+    commit: Oid,
+    tip: Oid,
+) -> Result<(), Error> {
+    if git_utils::is_commit_on_branch(repo, commit, tip)? {
+        return Ok(());
+    }
+    Err(Error::new(format!(
+        "commit {} is not reachable from branch {} (at {})",
+        commit, branch, tip
+    )))
+}
+
+// Borrow the objects of the cache repository in `cache_dir`, in memory only unless `persist`
+// is set, then through the alternates of the workspace repository
+pub fn borrow_cache_objects(
+    repo: &Repository,
+    cache_dir: &Path,
+    persist: bool,
+) -> Result<(), Error> {
+    let cache_git_dir = cache_dir.join(".git");
+    if persist {
+        alternates::add(repo.workdir().unwrap(), &cache_git_dir)?;
+        register_workspace_repo(repo.workdir().unwrap())?;
+    }
+    // The object database of an open repository does not pick up new alternates
+    repo.odb()?
+        .add_disk_alternate(&cache_git_dir.join("objects").to_string_lossy())?;
+    Ok(())
+}
+
+pub fn fetch_with_alternate(
+    repo: &Repository,
+    refs: &[&str],
+    remote_url: &str,
+    remote_name: Option<&str>,
+) -> Result<Oid, Error> {
     // Set up global cache
     log::debug!(
         "set up git global cache for repository {}",
         repo.path().display()
     );
-    let cache_dir = ensure_cache_dir()
-        .join("git")
-        .join(string_to_base64(&remote_url.to_string()));
-    log::debug!("create cache repository");
-    let cache_repo = open_or_create_repo(&cache_dir)?;
-    fetch_repository(&cache_repo, &remote_url, refs, remote_name)?;
+    let (cache_dir, fetch_head) = fetch_into_cache(refs, remote_url, remote_name)?;
 
-    // The objects will be fetched from object database of cache repository
-    add_alternate(&repo.workdir().unwrap(), &cache_dir.join(".git"))?;
-    let fetch_head = fetch_repository(&repo, &remote_url, refs, remote_name)?;
+    // The workspace repository borrows the fetched objects instead of fetching them again
+    borrow_cache_objects(repo, &cache_dir, true)?;
+    git_utils::ensure_remote(repo, remote_name.unwrap_or("origin"), remote_url)?;
     Ok(fetch_head)
 }
 
+// Branch names and their tips
+type Heads = Vec<(String, Oid)>;
+
+// Branch tips of the remotes, each remote is listed once per sync and only fetched from if a
+// tip is not available locally
+#[derive(Debug, Default)]
+pub struct RemoteHeads {
+    heads: Mutex<HashMap<String, Option<Heads>>>,
+}
+
+impl RemoteHeads {
+    // None if the remote could not be listed or has no such branch, it is fetched then
+    pub fn tip(&self, url: &str, branch: &str) -> Option<Oid> {
+        let mut heads = self.heads.lock();
+        let heads = heads.entry(url.to_string()).or_insert_with(|| {
+            git_utils::list_remote_heads(url)
+                .map_err(|e| log::debug!("failed to list heads of {}: {}", url, e.message))
+                .ok()
+        });
+        heads
+            .as_ref()?
+            .iter()
+            .find(|(name, _)| name == branch)
+            .map(|(_, tip)| *tip)
+    }
+}
+
+// How a component tracking a branch is updated when the working copy already has commits
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SyncStrategy {
+    // Only move forward, a diverged branch is left alone
+    FfOnly,
+    // Replay local commits onto the upstream branch
+    Rebase,
+    // Check out the upstream branch, refused if local commits would be lost
+    #[default]
+    Reset,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SyncOptions {
+    // Fetch directly into the workspace without going through the global cache
+    pub no_cache: bool,
+    // Copy the objects borrowed from the global cache into the workspace after fetching
+    pub dissociate: bool,
+    // Check out even if local changes or unpushed commits would be lost
+    pub force: bool,
+    // Stash local changes before checking out and re-apply them afterwards
+    pub autostash: bool,
+    pub strategy: SyncStrategy,
+    // Only warn about pinned commits which are not on their branch
+    pub allow_unreachable: bool,
+    // Plan without touching the workspace, the plan is recorded in the report
+    pub dry_run: bool,
+    pub plan_format: PlanFormat,
+    // Never fetch, revisions are resolved from the cache and workspace repositories
+    pub offline: bool,
+    // Fail instead of updating the remote of a component whose url changed
+    pub strict_urls: bool,
+    // Remove the components which were dropped from the configuration after syncing
+    pub prune: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncOutcome {
+    CheckedOut,
+    UpToDate,
+    FastForwarded,
+    Rebased(usize),
+    // The rebase stopped at a conflict, it is left in progress for the user to resolve
+    RebaseConflicts,
+    Reset,
+    Skipped(String),
+    // The revision is neither in the cache nor in the workspace, only when offline
+    Missing(String),
+    // Not started as the sync was interrupted
+    Untouched,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StashOutcome {
+    Reapplied,
+    // Re-applying left conflicts, the stash is kept as well
+    Conflicts,
+    // Not re-applied as the component is in the middle of a rebase, or applying it failed
+    Kept,
+}
+
+impl SyncOutcome {
+    fn needs_attention(&self) -> bool {
+        matches!(
+            self,
+            SyncOutcome::Skipped(_) | SyncOutcome::RebaseConflicts | SyncOutcome::Missing(_)
+        )
+    }
+}
+
+impl fmt::Display for SyncOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncOutcome::CheckedOut => write!(f, "{}", "checked out".green()),
+            SyncOutcome::UpToDate => write!(f, "{}", "up to date".green()),
+            SyncOutcome::FastForwarded => write!(f, "{}", "fast-forwarded".green()),
+            SyncOutcome::Rebased(n) => write!(f, "{}", format!("rebased {} commits", n).green()),
+            SyncOutcome::RebaseConflicts => write!(
+                f,
+                "{}",
+                "conflict left for manual resolution, resolve it and run \"git rebase --continue\""
+                    .red()
+            ),
+            SyncOutcome::Reset => write!(f, "{}", "reset".green()),
+            SyncOutcome::Skipped(reason) => write!(f, "{}", format!("skipped, {}", reason).red()),
+            SyncOutcome::Missing(revision) => {
+                write!(f, "{}", format!("{} is missing locally", revision).red())
+            }
+            SyncOutcome::Untouched => write!(f, "{}", "left untouched, interrupted".red()),
+        }
+    }
+}
+
+impl fmt::Display for StashOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StashOutcome::Reapplied => {
+                write!(f, "{}", "local changes stashed and re-applied".green())
+            }
+            StashOutcome::Conflicts => write!(
+                f,
+                "{}",
+                "local changes re-applied with conflicts and kept in the stash".yellow()
+            ),
+            StashOutcome::Kept => write!(f, "{}", "local changes kept in the stash".yellow()),
+        }
+    }
+}
+
+// What a sync does to a component
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncAction {
+    Clone,
+    Keep,
+    FastForward,
+    Rebase,
+    Reset,
+    Skip,
+    // A component dropped from the configuration, with --prune
+    Remove,
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncAction::Clone => write!(f, "clone"),
+            SyncAction::Keep => write!(f, "keep"),
+            SyncAction::FastForward => write!(f, "fast-forward"),
+            SyncAction::Rebase => write!(f, "rebase"),
+            SyncAction::Reset => write!(f, "reset"),
+            SyncAction::Skip => write!(f, "skip"),
+            SyncAction::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentPlan {
+    pub name: String,
+    pub path: PathBuf,
+    pub url: String,
+    pub action: SyncAction,
+    // Commit checked out now, none if the component is not cloned yet
+    pub from: Option<String>,
+    pub to: String,
+    // Local changes are stashed before and re-applied after updating
+    pub stash: bool,
+    // Why the component is skipped
+    pub reason: Option<String>,
+    // The url the remote points at now if it changed
+    pub previous_url: Option<String>,
+    // Where the repository is moved from as its path changed
+    pub moved_from: Option<PathBuf>,
+}
+
+impl fmt::Display for ComponentPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let short = |oid: &str| oid.chars().take(8).collect::<String>();
+        match (&self.action, &self.from) {
+            (SyncAction::Skip, _) => write!(
+                f,
+                "{}, {}",
+                "skip".red(),
+                self.reason.as_deref().unwrap_or_default()
+            )?,
+            (SyncAction::Remove, _) => write!(f, "{}", "remove".red())?,
+            (SyncAction::Keep, _) => {
+                write!(f, "{} at {}", "keep".green(), short(&self.to))?;
+                if let Some(reason) = &self.reason {
+                    write!(f, ", {}", reason)?;
+                }
+            }
+            (action, Some(from)) => write!(
+                f,
+                "{} {} -> {}",
+                action.to_string().yellow(),
+                short(from),
+                short(&self.to)
+            )?,
+            (action, None) => write!(f, "{} {}", action.to_string().yellow(), short(&self.to))?,
+        }
+        if self.stash {
+            write!(f, ", stash local changes")?;
+        }
+        if let Some(moved_from) = &self.moved_from {
+            write!(f, ", move from {}", moved_from.display())?;
+        }
+        if let Some(previous_url) = &self.previous_url {
+            write!(f, ", change url from {} to {}", previous_url, self.url)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PlanFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+// What happened to each component during a sync, or what would happen in a dry run
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    outcomes: Mutex<Vec<(String, SyncOutcome, Option<StashOutcome>)>>,
+    plans: Mutex<Vec<ComponentPlan>>,
+    // What else happened to a component, e.g. its url changed
+    notes: Mutex<Vec<(String, String)>>,
+}
+
+impl SyncReport {
+    pub fn record_note(&self, name: &str, note: String) {
+        self.notes.lock().push((name.to_string(), note));
+    }
+
+    pub fn record_plan(&self, plan: ComponentPlan) {
+        self.plans.lock().push(plan);
+    }
+
+    pub fn plans(&self) -> Vec<ComponentPlan> {
+        let mut plans = self.plans.lock().clone();
+        plans.sort_by(|a, b| a.name.cmp(&b.name));
+        plans
+    }
+
+    pub fn print_plan(&self, format: PlanFormat) -> Result<(), Error> {
+        let plans = self.plans();
+        match format {
+            PlanFormat::Text => {
+                println!("Plan:");
+                for plan in plans {
+                    println!("  {}: {}", plan.name, plan);
+                }
+            }
+            PlanFormat::Json => println!("{}", serde_json::to_string_pretty(&plans)?),
+        }
+        Ok(())
+    }
+
+    pub fn record(&self, name: &str, outcome: SyncOutcome, stash: Option<StashOutcome>) {
+        self.outcomes
+            .lock()
+            .push((name.to_string(), outcome, stash));
+    }
+
+    // Components whose revision could not be found offline
+    pub fn missing(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .outcomes
+            .lock()
+            .iter()
+            .filter(|(_, outcome, _)| matches!(outcome, SyncOutcome::Missing(_)))
+            .map(|(name, _, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // Components which were not started as the sync was interrupted
+    pub fn untouched(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .outcomes
+            .lock()
+            .iter()
+            .filter(|(_, outcome, _)| *outcome == SyncOutcome::Untouched)
+            .map(|(name, _, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // Number of components which were not updated or need to be resolved by the user
+    pub fn needs_attention(&self) -> usize {
+        self.outcomes
+            .lock()
+            .iter()
+            .filter(|(_, outcome, _)| outcome.needs_attention())
+            .count()
+    }
+
+    pub fn print(&self) {
+        let mut outcomes = self.outcomes.lock().clone();
+        outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+        let notes = self.notes.lock().clone();
+        println!("Summary:");
+        for (name, outcome, stash) in outcomes {
+            let mut line = format!("  {}: {}", name, outcome);
+            if let Some(stash) = stash {
+                line.push_str(&format!(", {}", stash));
+            }
+            for (_, note) in notes.iter().filter(|(n, _)| *n == name) {
+                line.push_str(&format!(", {}", note.yellow()));
+            }
+            println!("{}", line);
+        }
+    }
+}
+
+// How the working copy of a component is brought to the target commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Plan {
+    // A new repository without any commit checked out
+    Clone,
+    // HEAD is the target or contains it already
+    Keep,
+    FastForward,
+    Rebase,
+    Reset,
+    // The branch has local commits and the strategy only allows fast-forwards
+    Diverged,
+}
+
+impl Plan {
+    fn checks_out(&self) -> bool {
+        matches!(self, Plan::Clone | Plan::FastForward | Plan::Reset)
+    }
+}
+
+impl Plan {
+    fn action(&self) -> SyncAction {
+        match self {
+            Plan::Clone => SyncAction::Clone,
+            Plan::Keep => SyncAction::Keep,
+            Plan::FastForward => SyncAction::FastForward,
+            Plan::Rebase => SyncAction::Rebase,
+            Plan::Reset => SyncAction::Reset,
+            Plan::Diverged => SyncAction::Skip,
+        }
+    }
+}
+
+// How local work in a component is handled before checking out
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LocalWork {
+    None,
+    Stash,
+    Blocking(String),
+}
+
 #[derive(Copy, Clone)]
-pub struct ComponentSyncVisitor {}
+pub struct ComponentSyncVisitor<'a> {
+    options: SyncOptions,
+    // Name of the remote to fetch from, branches track their counterparts on it
+    remote: &'a str,
+    report: &'a SyncReport,
+    heads: &'a RemoteHeads,
+    // Components to sync, all of them if none
+    selection: Option<&'a Selection>,
+    // Where the components were after the last sync, they are moved if their paths changed
+    state: Option<&'a WorkspaceState>,
+}
 
-impl ComponentSyncVisitor {
-    pub fn new() -> Self {
-        ComponentSyncVisitor {}
+impl<'a> ComponentSyncVisitor<'a> {
+    pub fn new(
+        options: SyncOptions,
+        remote: &'a str,
+        report: &'a SyncReport,
+        heads: &'a RemoteHeads,
+    ) -> Self {
+        ComponentSyncVisitor {
+            options,
+            remote,
+            report,
+            heads,
+            selection: None,
+            state: None,
+        }
+    }
+
+    // Record where the components are in the workspace state and relocate them from there
+    pub fn track(mut self, state: &'a WorkspaceState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    // Only sync the selected components, the others are walked but left untouched
+    pub fn select(mut self, selection: &'a Selection) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+
+    fn fetch<'r>(
+        &self,
+        repo: &'r Repository,
+        refs: &[&str],
+        remote_url: &'r str,
+        remote_name: Option<&str>,
+    ) -> Result<Oid, Error> {
+        if self.options.no_cache {
+            Ok(fetch_repository(repo, remote_url, refs, remote_name)?.id())
+        } else {
+            fetch_with_alternate(repo, refs, remote_url, remote_name)
+        }
+    }
+
+    // Fetch `refs` and return the fetched commit. A dry run only fetches into the global cache
+    // and borrows its objects in memory, nothing is written to the workspace.
+    fn fetch_refs(
+        &self,
+        repo: &Repository,
+        refs: &[&str],
+        url: &str,
+        remote: &str,
+    ) -> Result<Oid, Error> {
+        if !self.options.dry_run {
+            return self.fetch(repo, refs, url, Some(remote));
+        }
+        let (cache_dir, fetch_head) = fetch_into_cache(refs, url, Some(remote))?;
+        borrow_cache_objects(repo, &cache_dir, false)?;
+        Ok(fetch_head)
+    }
+
+    // Whether `oid` is in the workspace repository already, or in the cache repository whose
+    // objects are borrowed then, so that it does not need to be fetched
+    fn find_locally(&self, repo: &Repository, url: &str, oid: Oid) -> Result<bool, Error> {
+        if repo.find_commit(oid).is_ok() {
+            return Ok(true);
+        }
+        if self.options.no_cache {
+            return Ok(false);
+        }
+        let cache_dir = cache_repo_dir(url)?;
+        match Repository::open(&cache_dir) {
+            Ok(cache_repo) if cache_repo.find_commit(oid).is_ok() => {}
+            _ => return Ok(false),
+        }
+        log::debug!("found {} of {} in the global cache", oid, url);
+        borrow_cache_objects(repo, &cache_dir, !self.options.dry_run)?;
+        Ok(true)
+    }
+
+    // The tip of `branch`, fetched only if the remote moved to a commit which is not local
+    fn fetch_branch(
+        &self,
+        repo: &Repository,
+        branch: &str,
+        url: &str,
+        remote: &str,
+    ) -> Result<Oid, Error> {
+        let tip = match self.heads.tip(url, branch) {
+            Some(tip) if self.find_locally(repo, url, tip)? => tip,
+            _ => return self.fetch_refs(repo, &[branch], url, remote),
+        };
+        log::debug!("{} of {} is at {} already, skip fetching", branch, url, tip);
+        if !self.options.no_cache && !self.options.dry_run {
+            // The tip might only be in the workspace, e.g. after pushing from it
+            let cache_dir = cache_repo_dir(url)?;
+            match Repository::open(&cache_dir) {
+                Ok(cache_repo) if cache_repo.find_commit(tip).is_ok() => {
+                    let _cache_lock = lock_cache_repo(&cache_dir, url)?;
+                    record_cache_tip(&cache_repo, Some(remote), branch, tip)?;
+                }
+                _ => {}
+            }
+        }
+        if !self.options.dry_run {
+            git_utils::ensure_remote(repo, remote, url)?;
+        }
+        Ok(tip)
+    }
+
+    // Pin `commit` where it was fetched into, the cache repository of `url` if it is there or
+    // the workspace repository without the cache
+    fn pin_commit(&self, repo: &Repository, url: &str, commit: Oid) -> Result<(), Error> {
+        // A dry run changes nothing, the cache included
+        if self.options.dry_run {
+            return Ok(());
+        }
+        let pin = format!("{}{}", CACHE_PINS_REF_PREFIX, commit);
+        if self.options.no_cache {
+            if repo.find_reference(&pin).is_err() {
+                record_pin(repo, commit)?;
+            }
+            return Ok(());
+        }
+        let cache_dir = cache_repo_dir(url)?;
+        let cache_repo = match Repository::open(&cache_dir) {
+            Ok(cache_repo) => cache_repo,
+            Err(_) => return Ok(()),
+        };
+        if cache_repo.find_commit(commit).is_err() || cache_repo.find_reference(&pin).is_ok() {
+            return Ok(());
+        }
+        let _cache_lock = lock_cache_repo(&cache_dir, url)?;
+        record_pin(&cache_repo, commit)
+    }
+
+    // Fail unless the pinned commit is on its branch or that is allowed, returns whether it is
+    fn check_pinned_commit(
+        &self,
+        repo: &Repository,
+        name: &str,
+        branch: &str,
+        commit: Oid,
+        tip: Oid,
+    ) -> Result<bool, Error> {
+        let e = match verify_pinned_commit(repo, branch, commit, tip) {
+            Ok(()) => return Ok(true),
+            Err(e) => e,
+        };
+        if !self.options.allow_unreachable {
+            return Err(Error::new(format!("{}: {}", name, e.message)));
+        }
+        let warning = format!("{} {}: {}", "warning:".yellow(), name, e.message);
+        // Keep the plan printed by a dry run parseable
+        if self.options.dry_run {
+            eprintln!("{}", warning);
+        } else {
+            println!("{}", warning);
+        }
+        Ok(false)
+    }
+
+    // Resolve the target without fetching, from the workspace repository and the objects and
+    // branch tips in the cache repository. None if the revision is not there.
+    fn resolve_offline(
+        &self,
+        repo: &Repository,
+        name: &str,
+        git: &GitDependency,
+    ) -> Result<Option<Oid>, Error> {
+        let cache_dir = cache_repo_dir(&git.url)?;
+        let cache_repo = Repository::open(&cache_dir).ok();
+        if cache_repo.is_some() {
+            let objects_dir = cache_dir.join(".git").join("objects");
+            repo.odb()?
+                .add_disk_alternate(&objects_dir.to_string_lossy())?;
+        }
+
+        let tip = git.branch.as_ref().and_then(|branch| {
+            let refname = format!("refs/remotes/{}/{}", self.remote_name(git), branch);
+            cache_repo
+                .as_ref()
+                .and_then(|cache_repo| cache_repo.refname_to_id(&refname).ok())
+                .or_else(|| repo.refname_to_id(&refname).ok())
+        });
+        let target = match (&git.branch, &git.commit) {
+            (_, Some(commit)) => Oid::from_str(commit)?,
+            (Some(_), None) => match tip {
+                Some(tip) => tip,
+                None => return Ok(None),
+            },
+            (None, None) => {
+                return Err(Error {
+                    message: String::from("neither branch nor commit is specified"),
+                })
+            }
+        };
+        if repo.find_commit(target).is_err() {
+            return Ok(None);
+        }
+        if let (Some(branch), Some(_), Some(tip)) = (&git.branch, &git.commit, tip) {
+            self.check_pinned_commit(repo, name, branch, target, tip)?;
+        }
+        Ok(Some(target))
+    }
+
+    // The commit to check out, the branch is fetched and the pinned commit verified.
+    // None if the revision is not available offline.
+    fn resolve_target(
+        &self,
+        repo: &Repository,
+        name: &str,
+        git: &GitDependency,
+    ) -> Result<Option<Oid>, Error> {
+        if self.options.offline {
+            let target = self.resolve_offline(repo, name, git)?;
+            if let (Some(target), Some(_)) = (target, &git.commit) {
+                self.pin_commit(repo, &git.url, target)?;
+            }
+            return Ok(target);
+        }
+        let url = &git.url;
+        let remote = self.remote_name(git);
+        if let Some(branch) = &git.branch {
+            let fetch_head = self.fetch_branch(repo, branch, url, remote)?;
+            if !self.options.dry_run {
+                repo.reference(
+                    &format!("refs/remotes/{}/{}", remote, branch),
+                    fetch_head,
+                    true,
+                    &format!("Fetch {} from {}", branch, url),
+                )?;
+            }
+            let commit = match &git.commit {
+                Some(commit) => Oid::from_str(commit)?,
+                None => return Ok(Some(fetch_head)),
+            };
+            if !self.check_pinned_commit(repo, name, branch, commit, fetch_head)?
+                && !self.find_locally(repo, url, commit)?
+            {
+                self.fetch_refs(repo, &[&commit.to_string()], url, remote)?;
+            }
+            self.pin_commit(repo, url, commit)?;
+            Ok(Some(commit))
+        } else if let Some(commit) = &git.commit {
+            let oid = Oid::from_str(commit)?;
+            if !self.find_locally(repo, url, oid)? {
+                self.fetch_refs(repo, &[commit], url, remote)?;
+            }
+            self.pin_commit(repo, url, oid)?;
+            Ok(Some(oid))
+        } else {
+            Err(Error {
+                message: String::from("neither branch nor commit is specified"),
+            })
+        }
+    }
+
+    fn plan(
+        &self,
+        repo: &Repository,
+        head: Option<Oid>,
+        target: Oid,
+        strategy: SyncStrategy,
+    ) -> Result<Plan, Error> {
+        let head = match head {
+            Some(head) => head,
+            None => return Ok(Plan::Clone),
+        };
+        if head == target {
+            return Ok(Plan::Keep);
+        }
+        if strategy == SyncStrategy::Reset {
+            return Ok(Plan::Reset);
+        }
+        if repo.graph_descendant_of(target, head)? {
+            Ok(Plan::FastForward)
+        } else if repo.graph_descendant_of(head, target)? {
+            Ok(Plan::Keep)
+        } else if strategy == SyncStrategy::Rebase {
+            Ok(Plan::Rebase)
+        } else {
+            Ok(Plan::Diverged)
+        }
+    }
+
+    // Whether following `plan` would lose local changes or commits, and how to avoid it
+    fn assess(
+        &self,
+        repo: &Repository,
+        url: &str,
+        target: Oid,
+        plan: Plan,
+    ) -> Result<LocalWork, Error> {
+        match plan {
+            Plan::Clone | Plan::Keep => return Ok(LocalWork::None),
+            Plan::Diverged => {
+                return Ok(LocalWork::Blocking(String::from(
+                    "diverged from upstream, not a fast-forward",
+                )))
+            }
+            _ if self.options.force && plan.checks_out() => return Ok(LocalWork::None),
+            _ => {}
+        }
+
+        if plan == Plan::Reset {
+            // Commits in the cache repository were fetched, e.g. the previously pinned one
+            let cache_repo = if self.options.no_cache {
+                None
+            } else {
+                Repository::open(cache_repo_dir(url)?).ok()
+            };
+            let unpushed = git_utils::count_unpushed_commits(repo, target, cache_repo.as_ref())?;
+            if unpushed > 0 {
+                return Ok(LocalWork::Blocking(format!(
+                    "{} unpushed commit(s) on {}",
+                    unpushed,
+                    repo.head()?.shorthand().unwrap_or("HEAD")
+                )));
+            }
+        }
+        if !git_utils::has_local_changes(repo)? {
+            return Ok(LocalWork::None);
+        }
+        if !self.options.autostash {
+            return Ok(LocalWork::Blocking(String::from("local changes")));
+        }
+        Ok(LocalWork::Stash)
+    }
+
+    // Move the repository of a component whose path changed in .crane instead of cloning it
+    // again, local branches and stashes move along. Returns where it was.
+    fn relocate(&self, url: &str, target_dir: &Path) -> Result<Option<PathBuf>, Error> {
+        let state = match self.state {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        if target_dir.exists() {
+            return Ok(None);
+        }
+        let previous_dir = match state.previous_path(url)? {
+            Some(previous_dir) => previous_dir,
+            None => return Ok(None),
+        };
+        if previous_dir.starts_with(target_dir)
+            || target_dir.starts_with(&previous_dir)
+            || Repository::open(&previous_dir).is_err()
+        {
+            return Ok(None);
+        }
+        if self.options.dry_run {
+            return Ok(Some(previous_dir));
+        }
+
+        log::debug!(
+            "move {} to {}",
+            previous_dir.display(),
+            target_dir.display()
+        );
+        if let Some(parent) = target_dir.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&previous_dir, target_dir)?;
+        if !alternates::read(target_dir)?.is_empty() {
+            register_workspace_repo(target_dir)?;
+        }
+        Ok(Some(previous_dir))
+    }
+
+    // Point the remote at the url of the component if it changed, e.g. after moving to another
+    // host. Returns the url it pointed at before.
+    fn update_remote_url(
+        &self,
+        repo: &Repository,
+        name: &str,
+        git: &GitDependency,
+    ) -> Result<Option<String>, Error> {
+        let url = &git.url;
+        let remote = self.remote_name(git);
+        let previous_url = match git_utils::changed_remote_url(repo, remote, url)? {
+            Some(previous_url) => previous_url,
+            None => return Ok(None),
+        };
+        if self.options.strict_urls {
+            return Err(Error::new(format!(
+                "{}: remote {} points at {} instead of {}",
+                name, remote, previous_url, url
+            )));
+        }
+        if !self.options.dry_run {
+            log::debug!("change url of {} from {} to {}", name, previous_url, url);
+            repo.remote_set_url(remote, &GitUrl::parse(url)?.fetch_url())?;
+            self.report.record_note(
+                name,
+                format!("url changed from {} to {}", previous_url, url),
+            );
+        }
+        Ok(Some(previous_url))
+    }
+
+    // The remote providing the pinned revision, named in .crane or by --remote
+    fn remote_name<'g>(&'g self, git: &'g GitDependency) -> &'g str {
+        git.remote.as_deref().unwrap_or(self.remote)
+    }
+
+    // Set up the other remotes declared for the component, the remote providing the pinned
+    // revision may only be given a push url
+    fn set_up_remotes(
+        &self,
+        repo: &Repository,
+        name: &str,
+        git: &GitDependency,
+    ) -> Result<(), Error> {
+        let remotes = match &git.remotes {
+            Some(remotes) => remotes,
+            None => return Ok(()),
+        };
+        let pin_remote = self.remote_name(git);
+        for (remote, spec) in &remotes.0 {
+            let url = match (remote == pin_remote, &spec.url) {
+                (true, Some(url))
+                    if GitUrl::parse(url)?.canonical() != GitUrl::parse(&git.url)?.canonical() =>
+                {
+                    return Err(Error::new(format!(
+                        "{}: remote {} provides the pinned revision and should be at {}, not {}",
+                        name, remote, git.url, url
+                    )));
+                }
+                (true, _) => &git.url,
+                (false, Some(url)) => url,
+                (false, None) => {
+                    return Err(Error::new(format!(
+                        "{}: remote {} has no url",
+                        name, remote
+                    )));
+                }
+            };
+            git_utils::configure_remote(repo, remote, url, spec.push_url.as_deref())?;
+        }
+        Ok(())
+    }
+
+    // Open or create the workspace repository borrowing objects from the cache repository,
+    // which is what fetching does when online
+    fn borrow_from_cache(&self, target_dir: &Path, url: &str) -> Result<Repository, Error> {
+        open_or_create_repo(target_dir)?;
+        let cache_dir = cache_repo_dir(url)?;
+        if cache_dir.is_dir() {
+            alternates::add(target_dir, &cache_dir.join(".git"))?;
+            register_workspace_repo(target_dir)?;
+        }
+        // Reopen to pick up the alternates
+        Ok(Repository::open(target_dir)?)
+    }
+
+    // Check out `target` and point HEAD at it, through the tracking ref of the branch if any
+    fn checkout(&self, repo: &Repository, git: &GitDependency, target: Oid) -> Result<(), Error> {
+        git_utils::checkout_to_target(repo, &target.to_string(), self.options.force)?;
+        self.update_head(repo, git, target)
+    }
+
+    fn update_head(
+        &self,
+        repo: &Repository,
+        git: &GitDependency,
+        target: Oid,
+    ) -> Result<(), Error> {
+        if let Some(branch) = &git.branch {
+            let refname = format!("refs/heads/{}", branch);
+            let msg = match &git.commit {
+                Some(commit) => format!("Setting {} to certain commit {}", branch, commit),
+                None => format!("Setting {} to {}", branch, target),
+            };
+            // The ref is moved after checking out, the tree of HEAD is the base of the checkout
+            repo.reference(&refname, target, true, &msg)?;
+            log::debug!("{}", msg);
+            repo.set_head(&refname)?;
+            git_utils::set_branch_upstream(repo, branch, self.remote_name(git))?;
+        } else if let Some(commit) = &git.commit {
+            log::debug!("Set HEAD to {}", commit);
+            repo.set_head_detached(target)?;
+        }
+        Ok(())
     }
 }
 
 #[async_trait]
-impl ComponentVisitor for ComponentSyncVisitor {
+impl ComponentVisitor for ComponentSyncVisitor<'_> {
     async fn visit_solution(&self, id: ComponentID, root_dir: &PathBuf) -> Result<(), Error> {
         // A solution should be a git repository
         self.visit_git(id, root_dir).await
@@ -55,47 +1015,180 @@ impl ComponentVisitor for ComponentSyncVisitor {
         let name = comp.name.clone();
         let url = git.url.clone();
         let target_dir = root_dir.clone().join(&comp.target_dir);
+        if let Some(selection) = self.selection {
+            if !selection.includes(&name, &target_dir) {
+                log::debug!("{} is not selected, leave it untouched", name);
+                return Ok(());
+            }
+        }
+        // After Ctrl-C the components in progress are finished but no other one is started
+        if interrupt::interrupted() {
+            log::debug!("sync interrupted, leave {} untouched", name);
+            self.report.record(&name, SyncOutcome::Untouched, None);
+            return Ok(());
+        }
 
-        let repo = open_or_create_repo(&target_dir)?;
-        let fetch_head;
-
-        if let Some(branch) = &git.branch {
-            let refname = format!("refs/for/{}", branch);
-            fetch_head = fetch_with_alternate(&repo, &[branch], &url, Some("origin"))?;
-            let mut msg = format!("Setting {} to {}", branch, fetch_head.id());
+        let moved_from = self.relocate(&url, &target_dir)?;
+        // Recorded even if the sync of the component fails, so that it is not pruned
+        if let Some(state) = self.state {
+            state.record(&url, &target_dir)?;
+        }
+        // A dry run plans a moved component against its repository at the previous path
+        let repo_dir = match &moved_from {
+            Some(moved_from) if self.options.dry_run => moved_from.clone(),
+            _ => target_dir.clone(),
+        };
+        if let (Some(moved_from), false) = (&moved_from, self.options.dry_run) {
+            println!("Moved {} from {}", name, moved_from.display());
+            self.report
+                .record_note(&name, format!("moved from {}", moved_from.display()));
+        }
 
-            let mut reference = match repo.find_reference(&refname) {
-                Ok(r) => r,
-                Err(_) => repo.reference(&refname, fetch_head.id(), true, &msg)?,
-            };
-            if let Some(commit) = &git.commit {
-                // TODO: check if the commit exists on the branch
-                msg = format!("Setting {} to certain commit {}", branch, commit);
-                reference.set_target(Oid::from_str(&commit)?, &msg)?;
-                git_utils::checkout_to_target(&repo, &commit)?;
-            } else {
-                reference.set_target(fetch_head.id(), &msg)?;
-                git_utils::checkout_to_target(&repo, &fetch_head.id().to_string())?;
+        // A component which is not cloned yet is resolved against a scratch repository in a
+        // dry run, and offline as its revision might be missing
+        let scratch_dir;
+        let mut repo = match Repository::open(&repo_dir) {
+            Ok(repo) => repo,
+            Err(_) if self.options.dry_run || self.options.offline => {
+                scratch_dir = tempfile::tempdir()?;
+                Repository::init_bare(scratch_dir.path())?
             }
-            log::debug!("{}", msg);
-            repo.set_head(&refname)?;
-        } else if let Some(commit) = &git.commit {
-            log::debug!("Set HEAD to {}", commit);
-            fetch_with_alternate(&repo, &[&commit], &url, Some("origin"))?;
-            git_utils::checkout_to_target(&repo, &commit)?;
-            repo.set_head(&commit)?;
+            Err(_) => open_or_create_repo(&target_dir)?,
+        };
+        let previous_url = self.update_remote_url(&repo, &name, git)?;
+        if !self.options.dry_run {
+            self.set_up_remotes(&repo, &name, git)?;
+        }
+        if let (Some(branch), false) = (&git.branch, self.options.dry_run) {
+            git_utils::migrate_legacy_branch_ref(&repo, branch)?;
+        }
+        let target = match self.resolve_target(&repo, &name, git)? {
+            Some(target) => target,
+            None => {
+                let revision = git.commit.as_ref().or(git.branch.as_ref()).unwrap();
+                if self.options.dry_run {
+                    let head = repo.head().ok().and_then(|head| head.target());
+                    self.report.record_plan(ComponentPlan {
+                        name,
+                        path: target_dir,
+                        url,
+                        action: SyncAction::Skip,
+                        from: head.map(|oid| oid.to_string()),
+                        to: revision.clone(),
+                        stash: false,
+                        reason: Some(format!("{} is missing locally", revision)),
+                        previous_url,
+                        moved_from,
+                    });
+                } else {
+                    self.report
+                        .record(&name, SyncOutcome::Missing(revision.clone()), None);
+                }
+                return Ok(());
+            }
+        };
+        if self.options.offline && !self.options.dry_run {
+            repo = self.borrow_from_cache(&target_dir, &url)?;
+        }
+
+        // A pinned commit is always checked out as it is
+        let strategy = if git.branch.is_some() && git.commit.is_none() {
+            self.options.strategy
         } else {
-            return Err(Error {
-                message: String::from("neither branch nor commit is specified"),
+            SyncStrategy::Reset
+        };
+        let head = repo.head().ok().and_then(|head| head.target());
+        let plan = self.plan(&repo, head, target, strategy)?;
+        let local_work = self.assess(&repo, &url, target, plan)?;
+
+        if self.options.dry_run {
+            let reason = match &local_work {
+                LocalWork::Blocking(reason) => Some(reason.clone()),
+                _ => None,
+            };
+            self.report.record_plan(ComponentPlan {
+                name,
+                path: target_dir,
+                url,
+                action: match reason {
+                    Some(_) => SyncAction::Skip,
+                    None => plan.action(),
+                },
+                from: head.map(|oid| oid.to_string()),
+                to: target.to_string(),
+                stash: local_work == LocalWork::Stash,
+                reason,
+                previous_url,
+                moved_from,
             });
+            return Ok(());
+        }
+
+        match &local_work {
+            LocalWork::Blocking(reason) => {
+                println!("Skip {}: {}", name, reason);
+                self.report
+                    .record(&name, SyncOutcome::Skipped(reason.clone()), None);
+                return Ok(());
+            }
+            LocalWork::Stash => {
+                git_utils::stash_local_changes(&mut repo, &format!("crane autostash of {}", name))?;
+            }
+            LocalWork::None => {}
         }
 
+        let outcome = match plan {
+            Plan::Clone => {
+                self.checkout(&repo, git, target)?;
+                SyncOutcome::CheckedOut
+            }
+            Plan::Keep => {
+                // Keep the local commits of a branch which is ahead of the target
+                if head == Some(target) {
+                    self.update_head(&repo, git, target)?;
+                }
+                SyncOutcome::UpToDate
+            }
+            Plan::FastForward => {
+                self.checkout(&repo, git, target)?;
+                SyncOutcome::FastForwarded
+            }
+            Plan::Reset => {
+                self.checkout(&repo, git, target)?;
+                SyncOutcome::Reset
+            }
+            Plan::Rebase => match git_utils::rebase_onto(&repo, target)? {
+                RebaseResult::Rebased(n) => {
+                    let head = repo.head()?.target().unwrap();
+                    self.update_head(&repo, git, head)?;
+                    SyncOutcome::Rebased(n)
+                }
+                RebaseResult::Conflicts => SyncOutcome::RebaseConflicts,
+            },
+            Plan::Diverged => unreachable!("diverged components are skipped"),
+        };
+
+        let stash = match local_work {
+            LocalWork::Stash if outcome == SyncOutcome::RebaseConflicts => Some(StashOutcome::Kept),
+            LocalWork::Stash => Some(match git_utils::unstash_local_changes(&mut repo)? {
+                UnstashResult::Reapplied => StashOutcome::Reapplied,
+                UnstashResult::Conflicts => StashOutcome::Conflicts,
+                UnstashResult::Failed => StashOutcome::Kept,
+            }),
+            _ => None,
+        };
+        self.report.record(&name, outcome, stash);
+
         log::debug!("visit git component: {}", name);
         log::debug!(
             "checkout to {}",
             repo.head()?.target().map(|o| o.to_string()).unwrap()
         );
 
+        if self.options.dissociate && git_utils::dissociate(&target_dir)? {
+            log::debug!("dissociate {} from the global cache", name);
+        }
+
         Ok(())
     }
 }
@@ -1,12 +1,16 @@
 mod tests {
     use assert_cmd::prelude::*;
     use assert_fs::{prelude::*, TempDir};
-    use git2::Repository;
+    use git2::{Oid, Repository};
     use predicates::prelude::*;
-    use std::{path::PathBuf, process::Command};
+    use std::{
+        io::{BufRead, BufReader},
+        path::PathBuf,
+        process::{Command, Stdio},
+    };
     use test_log::test;
 
-    use crane::utils::{fs::copy_dir_to, test_utils};
+    use crane::utils::{fs::copy_dir_to, git_url::GitUrl, lock::FileLock, test_utils};
 
     #[test(tokio::test)]
     async fn test_sync_simple_with_url_and_without_dir() -> Result<(), Box<dyn std::error::Error>> {
@@ -110,6 +114,2129 @@ mod tests {
         Ok(())
     }
 
+    #[test(tokio::test)]
+    async fn test_sync_simple_with_local_path() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let target_dir = "test_sync_simple_with_local_path";
+
+        // A plain path is used as the url instead of file://
+        let mut cmd = Command::cargo_bin("crane")?;
+        cmd.arg("sync")
+            .arg("--url")
+            .arg(source_repo_dir.path().display().to_string())
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main");
+
+        let workdir = &TempDir::new()?;
+        cmd.current_dir(workdir).assert().success();
+
+        workdir
+            .child(target_dir)
+            .child("README.md")
+            .assert(predicate::path::exists());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_with_cache_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let cache_dir = TempDir::new()?;
+        let target_dir = "test_sync_with_cache_dir";
+        let mut cmd = Command::cargo_bin("crane")?;
+        cmd.arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(cache_dir.path());
+
+        let workdir = &TempDir::new()?;
+        cmd.current_dir(workdir).assert().success();
+
+        workdir
+            .child(target_dir)
+            .child("README.md")
+            .assert(predicate::path::exists());
+        cache_dir.child("git").assert(predicate::path::is_dir());
+        workdir
+            .child(target_dir)
+            .child(".git/objects/info/alternates")
+            .assert(predicate::str::contains(
+                cache_dir.path().display().to_string(),
+            ));
+
+        let source_url = format!("file://{}/.git", source_repo_dir.path().display());
+        Command::cargo_bin("crane")?
+            .args(["cache", "list", "--cache-dir"])
+            .arg(cache_dir.path())
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(source_url.as_str()));
+
+        // The cache repository is still used by the workspace, so it is kept
+        Command::cargo_bin("crane")?
+            .args(["cache", "prune", "--older-than", "0s", "--cache-dir"])
+            .arg(cache_dir.path())
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("still used by"));
+
+        // Whichever way the cache dir is spelled
+        let link_dir = TempDir::new()?;
+        let cache_link = link_dir.path().join("cache");
+        std::os::unix::fs::symlink(cache_dir.path(), &cache_link)?;
+        Command::cargo_bin("crane")?
+            .args(["cache", "prune", "--older-than", "0s", "--cache-dir"])
+            .arg(format!("{}/", cache_link.display()))
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("still used by"));
+
+        // Workspaces synced by an older crane are not registered
+        let cache_repo = cache_dir
+            .path()
+            .join("git")
+            .join(GitUrl::parse(&source_url)?.cache_key());
+        Repository::open(&cache_repo)?
+            .config()?
+            .remove("crane.registersworkspaces")?;
+        Command::cargo_bin("crane")?
+            .args(["cache", "prune", "--older-than", "0s", "--cache-dir"])
+            .arg(cache_dir.path())
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "might be used by workspaces synced by an older crane",
+            ));
+        assert!(cache_repo.is_dir());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_without_cache() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let cache_dir = TempDir::new()?;
+        let target_dir = "test_sync_without_cache";
+        let mut cmd = Command::cargo_bin("crane")?;
+        cmd.arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--no-cache")
+            .env("CRANE_CACHE_DIR", cache_dir.path());
+
+        let workdir = &TempDir::new()?;
+        cmd.current_dir(workdir).assert().success();
+
+        workdir
+            .child(target_dir)
+            .child("README.md")
+            .assert(predicate::path::exists());
+        cache_dir.child("git").assert(predicate::path::missing());
+        workdir
+            .child(target_dir)
+            .child(".git/objects/info/alternates")
+            .assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_with_dissociate() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let cache_dir = TempDir::new()?;
+        let target_dir = "test_sync_with_dissociate";
+        let mut cmd = Command::cargo_bin("crane")?;
+        cmd.arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--dissociate")
+            .arg("--cache-dir")
+            .arg(cache_dir.path());
+
+        let workdir = &TempDir::new()?;
+        cmd.current_dir(workdir).assert().success();
+
+        cache_dir.child("git").assert(predicate::path::is_dir());
+        workdir
+            .child(target_dir)
+            .child(".git/objects/info/alternates")
+            .assert(predicate::path::missing());
+
+        // The workspace keeps working without the cache
+        cache_dir.close()?;
+        Command::cargo_bin("crane")?
+            .arg("status")
+            .arg(workdir.child(target_dir).path())
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("does not exist anymore").not());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_dissociate_after_sync() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let cache_dir = TempDir::new()?;
+        let target_dir = "test_dissociate_after_sync";
+        let workdir = &TempDir::new()?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        let alternates = workdir
+            .child(target_dir)
+            .child(".git/objects/info/alternates");
+        alternates.assert(predicate::path::exists());
+
+        Command::cargo_bin("crane")?
+            .arg("dissociate")
+            .arg(workdir.child(target_dir).path())
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Dissociated"));
+        alternates.assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_status_warns_about_missing_cache() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let cache_dir = TempDir::new()?;
+        let target_dir = "test_status_warns_about_missing_cache";
+        let workdir = &TempDir::new()?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        cache_dir.close()?;
+        Command::cargo_bin("crane")?
+            .arg("status")
+            .arg(workdir.child(target_dir).path())
+            .assert()
+            .stdout(predicate::str::contains("does not exist anymore"));
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_resync_and_doctor_fix_alternates() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let cache_root = TempDir::new()?;
+        let old_cache_dir = cache_root.child("old");
+        let target_dir = "test_resync_and_doctor_fix_alternates";
+        let workdir = &TempDir::new()?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(old_cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--cache-dir")
+            .arg(old_cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // Syncing again does not add another entry
+        let alternates = workdir
+            .child(target_dir)
+            .child(".git/objects/info/alternates");
+        let content = std::fs::read_to_string(alternates.path())?;
+        assert_eq!(content.lines().count(), 1);
+
+        // Move the cache and re-point the alternates to the new location
+        let new_cache_dir = cache_root.child("new");
+        std::fs::rename(old_cache_dir.path(), new_cache_dir.path())?;
+        Command::cargo_bin("crane")?
+            .arg("doctor")
+            .arg(workdir.child(target_dir).path())
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("missing"));
+        Command::cargo_bin("crane")?
+            .arg("doctor")
+            .arg(workdir.child(target_dir).path())
+            .arg("--fix")
+            .arg("--cache-dir")
+            .arg(new_cache_dir.path())
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("re-pointed"));
+        alternates.assert(predicate::str::contains(
+            new_cache_dir.path().display().to_string(),
+        ));
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_keeps_local_changes() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_keeps_local_changes";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // Change a tracked file locally while the remote moves on
+        let readme = workdir.child(target_dir).child("README.md");
+        readme.write_str("local change")?;
+        test_utils::modify_file_in_repo(
+            &source_repo_dir,
+            &PathBuf::from("README.2.md"),
+            "test",
+            true,
+            true,
+            true,
+        )?;
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .current_dir(workdir)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains("skipped, local changes"));
+        readme.assert("local change");
+        workdir
+            .child(target_dir)
+            .child("README.2.md")
+            .assert(predicate::path::missing());
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--autostash")
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "local changes stashed and re-applied",
+            ));
+        readme.assert("local change");
+        workdir
+            .child(target_dir)
+            .child("README.2.md")
+            .assert(predicate::path::exists());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_verifies_pinned_commit() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+
+        // A commit on another branch, which is not reachable from main
+        let repo = Repository::open(lib_repo_dir.path())?;
+        let parent = repo.head()?.peel_to_commit()?;
+        let signature = git2::Signature::now("test", "test@example.com")?;
+        let unreachable = repo.commit(
+            None,
+            &signature,
+            &signature,
+            "Off main",
+            &parent.tree()?,
+            &[&parent],
+        )?;
+        repo.branch("other", &repo.find_commit(unreachable)?, false)?;
+
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "{}", "branch": "main", "commit": "{}"}} }}"#,
+                lib_repo, unreachable
+            ),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let cache_dir = TempDir::new()?;
+        let target_dir = "test_sync_verifies_pinned_commit";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains(format!(
+                "commit {} is not reachable from branch main",
+                unreachable
+            )));
+
+        // Nothing is checked out by crane check
+        Command::cargo_bin("crane")?
+            .arg("check")
+            .arg(target_dir)
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains("not reachable from branch main"));
+        workdir
+            .child(target_dir)
+            .child("lib")
+            .child("README.md")
+            .assert(predicate::path::missing());
+
+        Command::cargo_bin("crane")?
+            .arg("check")
+            .arg(target_dir)
+            .arg("--allow-unreachable")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("warning:"));
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--allow-unreachable")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("warning:"));
+        let head = Repository::open(workdir.child(target_dir).child("lib").path())?
+            .head()?
+            .target()
+            .unwrap();
+        assert_eq!(head, unreachable);
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_dry_run() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "{}", "branch": "main"}} }}"#,
+                lib_repo
+            ),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_dry_run";
+        let output = Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--dry-run")
+            .arg("--format")
+            .arg("json")
+            .current_dir(workdir)
+            .output()?;
+        assert!(output.status.success());
+        let plan: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        assert_eq!(plan[0]["name"], "(main)");
+        assert_eq!(plan[0]["action"], "clone");
+        assert!(plan[0]["from"].is_null());
+        workdir.child(target_dir).assert(predicate::path::missing());
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+        let lib_dir = workdir.child(target_dir).child("lib");
+        let lib_head = Repository::open(lib_dir.path())?.head()?.target().unwrap();
+
+        // The remote moves on, the dry run neither fetches into nor checks out the workspace
+        test_utils::modify_file_in_repo(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.2.md"),
+            "test",
+            true,
+            true,
+            true,
+        )?;
+        let lib_remote_head = Repository::open(lib_repo_dir.path())?
+            .head()?
+            .target()
+            .unwrap();
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--dry-run")
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Plan:"))
+            .stdout(predicate::str::contains(format!(
+                "{} -> {}",
+                &lib_head.to_string()[..8],
+                &lib_remote_head.to_string()[..8]
+            )))
+            .stdout(predicate::str::contains("Summary:").not());
+        let repo = Repository::open(lib_dir.path())?;
+        assert_eq!(repo.head()?.target().unwrap(), lib_head);
+        assert_eq!(
+            repo.find_reference("refs/remotes/origin/main")?.target(),
+            Some(lib_head)
+        );
+
+        // Dirty components are reported as skipped
+        test_utils::modify_file_in_repo(
+            lib_dir.path(),
+            &PathBuf::from("README.md"),
+            "changed",
+            false,
+            false,
+            false,
+        )?;
+        let output = Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--dry-run")
+            .arg("--format")
+            .arg("json")
+            .current_dir(workdir)
+            .output()?;
+        assert!(output.status.success());
+        let plan: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        let lib = plan
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| p["name"] == "lib")
+            .unwrap();
+        assert_eq!(lib["action"], "skip");
+        assert_eq!(lib["reason"], "local changes");
+        assert_eq!(lib["to"], lib_remote_head.to_string());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_offline() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "{}", "branch": "main"}} }}"#,
+                lib_repo
+            ),
+        )
+        .unwrap();
+        let main_url = format!("file://{}/.git", main_repo_dir.path().display());
+
+        let workdir = &TempDir::new()?;
+        let cache_dir = TempDir::new()?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(&main_url)
+            .arg("online")
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // Without the remotes everything comes from the cache
+        let moved = |dir: &TempDir| dir.path().with_extension("moved");
+        std::fs::rename(lib_repo_dir.path(), moved(&lib_repo_dir))?;
+        std::fs::rename(main_repo_dir.path(), moved(&main_repo_dir))?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(&main_url)
+            .arg("offline")
+            .arg("--branch")
+            .arg("main")
+            .arg("--offline")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+        workdir
+            .child("offline")
+            .child("lib")
+            .child("README.md")
+            .assert(predicate::path::exists());
+        let head = |dir: &str| {
+            Repository::open(workdir.child(dir).child("lib").path())
+                .unwrap()
+                .head()
+                .unwrap()
+                .target()
+        };
+        assert_eq!(head("offline"), head("online"));
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg("file:///nonexistent/repo.git")
+            .arg("missing")
+            .arg("--branch")
+            .arg("main")
+            .arg("--offline")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains(
+                "revisions of (main) are missing locally",
+            ));
+        workdir.child("missing").assert(predicate::path::missing());
+
+        std::fs::rename(moved(&lib_repo_dir), lib_repo_dir.path())?;
+        std::fs::rename(moved(&main_repo_dir), main_repo_dir.path())?;
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_cache_gc_keeps_pinned_commits() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        // Pinned on a side branch, so that no branch tip of the cache reaches it
+        let repo = Repository::open(lib_repo_dir.path())?;
+        let parent = repo.head()?.peel_to_commit()?;
+        let signature = git2::Signature::now("test", "test@example.com")?;
+        let pinned = repo.commit(
+            None,
+            &signature,
+            &signature,
+            "Off main",
+            &parent.tree()?,
+            &[&parent],
+        )?;
+        repo.branch("side", &repo.find_commit(pinned)?, false)?;
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "{}", "commit": "{}"}} }}"#,
+                lib_repo, pinned
+            ),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let cache_dir = TempDir::new()?;
+        let target_dir = "test_cache_gc_keeps_pinned_commits";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // A dry run does not pin the commit
+        let lib_cache_repo = Repository::open(
+            cache_dir
+                .path()
+                .join("git")
+                .join(GitUrl::parse(&lib_repo)?.cache_key()),
+        )?;
+        let pin = format!("refs/crane/pins/{}", pinned);
+        lib_cache_repo.find_reference(&pin)?.delete()?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--dry-run")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir.child(target_dir))
+            .assert()
+            .success();
+        assert!(lib_cache_repo.find_reference(&pin).is_err());
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir.child(target_dir))
+            .assert()
+            .success();
+        assert!(lib_cache_repo.find_reference(&pin).is_ok());
+
+        Command::cargo_bin("crane")?
+            .arg("cache")
+            .arg("gc")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // The workspace still finds the pinned commit it borrows from the cache
+        Command::new("git")
+            .arg("fsck")
+            .current_dir(workdir.child(target_dir).child("lib").path())
+            .assert()
+            .success();
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_rolls_back_pinned_commit() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        let repo = Repository::open(lib_repo_dir.path())?;
+        let earlier = repo.head()?.peel_to_commit()?;
+        let signature = git2::Signature::now("test", "test@example.com")?;
+        let later = repo.commit(
+            None,
+            &signature,
+            &signature,
+            "Off main",
+            &earlier.tree()?,
+            &[&earlier],
+        )?;
+        repo.branch("side", &repo.find_commit(later)?, false)?;
+        let crane_file = |commit: Oid| {
+            format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "{}", "commit": "{}"}} }}"#,
+                lib_repo, commit
+            )
+        };
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &crane_file(later),
+        )
+        .unwrap();
+
+        // The previous pin is not local work, whether it was fetched through the cache or not
+        let workdir = &TempDir::new()?;
+        let cache_dir = TempDir::new()?;
+        for (target_dir, args) in [
+            ("with_cache", vec![]),
+            ("without_cache", vec!["--no-cache"]),
+        ] {
+            Command::cargo_bin("crane")?
+                .arg("sync")
+                .arg("--url")
+                .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+                .arg(target_dir)
+                .arg("--branch")
+                .arg("main")
+                .args(&args)
+                .arg("--cache-dir")
+                .arg(cache_dir.path())
+                .current_dir(workdir)
+                .assert()
+                .success();
+        }
+        test_utils::modify_file_in_repo(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &crane_file(earlier.id()),
+            false,
+            true,
+            true,
+        )?;
+        for (target_dir, args) in [
+            ("with_cache", vec![]),
+            ("without_cache", vec!["--no-cache"]),
+        ] {
+            Command::cargo_bin("crane")?
+                .arg("sync")
+                .arg(target_dir)
+                .args(&args)
+                .arg("--cache-dir")
+                .arg(cache_dir.path())
+                .current_dir(workdir)
+                .assert()
+                .success()
+                .stdout(predicate::str::contains("unpushed").not());
+            let head = Repository::open(workdir.child(target_dir).child("lib").path())?
+                .head()?
+                .target();
+            assert_eq!(head, Some(earlier.id()));
+        }
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_skips_fetch_of_local_commit() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        let lib_head = Repository::open(lib_repo_dir.path())?
+            .head()?
+            .target()
+            .unwrap();
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "{}", "commit": "{}"}} }}"#,
+                lib_repo, lib_head
+            ),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let cache_dir = TempDir::new()?;
+        let target_dir = "test_sync_skips_fetch_of_local_commit";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // The pinned commit is there already, the remote is not needed anymore
+        let moved = lib_repo_dir.path().with_extension("moved");
+        std::fs::rename(lib_repo_dir.path(), &moved)?;
+        std::fs::remove_dir_all(workdir.child(target_dir).child("lib").path())?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+        let head = Repository::open(workdir.child(target_dir).child("lib").path())?
+            .head()?
+            .target();
+        assert_eq!(head, Some(lib_head));
+
+        std::fs::rename(&moved, lib_repo_dir.path())?;
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_selected_components() -> Result<(), Box<dyn std::error::Error>> {
+        let lib1_repo_dir = TempDir::new()?;
+        let lib1_repo = test_utils::create_git_repo_in_dir(
+            lib1_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib1",
+        )
+        .unwrap();
+        let lib2_repo_dir = TempDir::new()?;
+        let lib2_repo = test_utils::create_git_repo_in_dir(
+            lib2_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib2",
+        )
+        .unwrap();
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ \
+                    "lib1": {{"type": "git", "url": "{}", "branch": "main"}}, \
+                    "lib2": {{"type": "git", "url": "{}", "branch": "main"}} \
+                }}"#,
+                lib1_repo, lib2_repo
+            ),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_selected_components";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        for dir in [&lib1_repo_dir, &lib2_repo_dir] {
+            test_utils::modify_file_in_repo(
+                dir.path(),
+                &PathBuf::from("README.2.md"),
+                "test",
+                true,
+                true,
+                true,
+            )?;
+        }
+        let remote_head = |dir: &TempDir| {
+            Repository::open(dir.path())
+                .unwrap()
+                .head()
+                .unwrap()
+                .target()
+        };
+        let head = |name: &str| {
+            Repository::open(workdir.child(target_dir).child(name).path())
+                .unwrap()
+                .head()
+                .unwrap()
+                .target()
+        };
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("lib1")
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("lib1:"))
+            .stdout(predicate::str::contains("lib2:").not());
+        assert_eq!(head("lib1"), remote_head(&lib1_repo_dir));
+        assert_ne!(head("lib2"), remote_head(&lib2_repo_dir));
+
+        // In the root of the solution every target is a component
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("lib*")
+            .current_dir(workdir.child(target_dir))
+            .assert()
+            .success();
+        assert_eq!(head("lib2"), remote_head(&lib2_repo_dir));
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("missing")
+            .current_dir(workdir.child(target_dir))
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains("no component matches missing"));
+
+        // A directory outside the solution is the directory of another solution
+        let other_dir = "other_solution";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(other_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+        test_utils::modify_file_in_repo(
+            lib1_repo_dir.path(),
+            &PathBuf::from("README.3.md"),
+            "test",
+            true,
+            true,
+            true,
+        )?;
+        let other_head = |name: &str| {
+            Repository::open(workdir.child(other_dir).child(name).path())
+                .unwrap()
+                .head()
+                .unwrap()
+                .target()
+        };
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(format!("../{}", other_dir))
+            .arg("lib1")
+            .current_dir(workdir.child(target_dir))
+            .assert()
+            .success();
+        assert_eq!(other_head("lib1"), remote_head(&lib1_repo_dir));
+        assert_ne!(head("lib1"), remote_head(&lib1_repo_dir));
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(workdir.child(target_dir).path())
+            .current_dir(workdir.child(target_dir))
+            .assert()
+            .success();
+        assert_eq!(head("lib1"), remote_head(&lib1_repo_dir));
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_updates_changed_url() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        let main_repo_dir = TempDir::new()?;
+        let deps = |url: &str| {
+            format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "{}", "branch": "main"}} }}"#,
+                url
+            )
+        };
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &deps(&lib_repo),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_updates_changed_url";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // The lib moves to another host
+        let new_lib_repo_dir = TempDir::new()?;
+        copy_dir_to(lib_repo_dir.path(), new_lib_repo_dir.path())?;
+        let new_lib_repo = format!("file://{}", new_lib_repo_dir.path().display());
+        std::fs::write(main_repo_dir.path().join(".crane"), deps(&new_lib_repo))?;
+        test_utils::modify_file_in_repo(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            "",
+            true,
+            true,
+            true,
+        )?;
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--strict-urls")
+            .current_dir(workdir)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains(format!(
+                "lib: remote origin points at {} instead of {}",
+                lib_repo, new_lib_repo
+            )));
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "url changed from {} to {}",
+                lib_repo, new_lib_repo
+            )));
+        let repo = Repository::open(workdir.child(target_dir).child("lib").path())?;
+        assert_eq!(
+            repo.find_remote("origin")?.url(),
+            Some(new_lib_repo.as_str())
+        );
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_moves_renamed_component() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        let main_repo_dir = TempDir::new()?;
+        let deps = |path: &str| {
+            format!(
+                r#"deps = {{ "{}": {{"type": "git", "url": "{}", "branch": "main"}} }}"#,
+                path, lib_repo
+            )
+        };
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &deps("lib"),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_moves_renamed_component";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // Local work which a fresh clone would lose
+        let old_dir = workdir.child(target_dir).child("lib");
+        let repo = Repository::open(old_dir.path())?;
+        let head = repo.head()?.peel_to_commit()?;
+        repo.branch("work", &head, false)?;
+
+        std::fs::write(main_repo_dir.path().join(".crane"), deps("third_party/lib"))?;
+        test_utils::modify_file_in_repo(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            "",
+            true,
+            true,
+            true,
+        )?;
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("moved from"));
+        old_dir.assert(predicate::path::missing());
+        let repo = Repository::open(
+            workdir
+                .child(target_dir)
+                .child("third_party")
+                .child("lib")
+                .path(),
+        )?;
+        assert!(repo.find_branch("work", git2::BranchType::Local).is_ok());
+
+        // The state of the workspace is not part of the solution
+        let main_repo = Repository::open(workdir.child(target_dir).path())?;
+        assert!(main_repo.status_should_ignore(&PathBuf::from(".crane_workspace/state.json"))?);
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_prunes_dropped_components() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        let main_repo_dir = TempDir::new()?;
+        let deps = |paths: &[&str]| {
+            let deps: Vec<String> = paths
+                .iter()
+                .map(|path| {
+                    format!(
+                        r#""{}": {{"type": "git", "url": "{}", "branch": "main"}}"#,
+                        path, lib_repo
+                    )
+                })
+                .collect();
+            format!("deps = {{ {} }}", deps.join(", "))
+        };
+        // A commit pin has no remote-tracking branch, its commit is only in the cache
+        let lib_head = Repository::open(lib_repo_dir.path())?
+            .head()?
+            .target()
+            .unwrap();
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                "{}\ndeps[\"pinned\"] = {{\"type\": \"git\", \"url\": \"{}\", \"commit\": \"{}\"}}",
+                deps(&["lib", "tool", "extra"]),
+                lib_repo,
+                lib_head
+            ),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_prunes_dropped_components";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // Work which was never pushed
+        let tool_dir = workdir.child(target_dir).child("tool");
+        test_utils::modify_file_in_repo(
+            tool_dir.path(),
+            &PathBuf::from("README.md"),
+            "tool",
+            false,
+            true,
+            true,
+        )?;
+        std::fs::write(main_repo_dir.path().join(".crane"), deps(&["lib"]))?;
+        test_utils::modify_file_in_repo(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            "",
+            true,
+            true,
+            true,
+        )?;
+
+        // A dry run walks the configuration in the workspace
+        let crane_file = workdir.child(target_dir).child(".crane");
+        crane_file.write_str(&deps(&["lib"]))?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--prune")
+            .arg("--dry-run")
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("extra: "))
+            .stdout(predicate::str::contains("pinned: "))
+            .stdout(predicate::str::contains("remove"))
+            .stdout(predicate::str::contains("unpushed commits on main"));
+        workdir
+            .child(target_dir)
+            .child("extra")
+            .assert(predicate::path::exists());
+        Command::new("git")
+            .args(["checkout", "--", ".crane"])
+            .current_dir(workdir.child(target_dir).path())
+            .assert()
+            .success();
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--prune")
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Removed extra"))
+            .stdout(predicate::str::contains("Removed pinned"))
+            .stdout(predicate::str::contains("Kept tool"))
+            .stdout(predicate::str::contains("unpushed commits on main"));
+        workdir
+            .child(target_dir)
+            .child("extra")
+            .assert(predicate::path::missing());
+        workdir
+            .child(target_dir)
+            .child("pinned")
+            .assert(predicate::path::missing());
+        tool_dir.assert(predicate::path::exists());
+        workdir
+            .child(target_dir)
+            .child("lib")
+            .assert(predicate::path::exists());
+
+        Command::cargo_bin("crane")?
+            .arg("prune")
+            .arg(target_dir)
+            .arg("--force")
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Removed tool"));
+        tool_dir.assert(predicate::path::missing());
+        workdir
+            .child(target_dir)
+            .child("lib")
+            .assert(predicate::path::exists());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_sets_up_remotes() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        // The fork is pushed to, the pinned revision comes from upstream
+        let fork_repo_dir = TempDir::new()?;
+        copy_dir_to(lib_repo_dir.path(), fork_repo_dir.path())?;
+        let fork_repo = format!("file://{}", fork_repo_dir.path().display());
+        let fork_push_url = format!("file://{}/push", fork_repo_dir.path().display());
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "{}", "branch": "main", "remote": "upstream",
+                    "remotes": {{"origin": {{"url": "{}", "push_url": "{}"}}, "mirror": "{}"}} }} }}"#,
+                lib_repo, fork_repo, fork_push_url, fork_repo
+            ),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_sets_up_remotes";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        let repo = Repository::open(workdir.child(target_dir).child("lib").path())?;
+        let upstream = repo.find_remote("upstream")?;
+        assert_eq!(upstream.url(), Some(lib_repo.as_str()));
+        let origin = repo.find_remote("origin")?;
+        assert_eq!(origin.url(), Some(fork_repo.as_str()));
+        assert_eq!(origin.pushurl(), Some(fork_push_url.as_str()));
+        assert_eq!(repo.find_remote("mirror")?.url(), Some(fork_repo.as_str()));
+        assert!(repo.find_reference("refs/remotes/upstream/main").is_ok());
+        assert_eq!(repo.config()?.get_string("branch.main.remote")?, "upstream");
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_resolves_relative_url() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        let lib_repo = test_utils::create_git_repo_in_dir(
+            lib_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        // Both repositories are in the same directory, as on a server
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "../../{}", "branch": "main"}} }}"#,
+                lib_repo_dir.path().file_name().unwrap().to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_resolves_relative_url";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        let lib_dir = workdir.child(target_dir).child("lib");
+        lib_dir.child("README.md").assert(predicate::path::exists());
+        let repo = Repository::open(lib_dir.path())?;
+        assert_eq!(repo.find_remote("origin")?.url(), Some(lib_repo.as_str()));
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_fetches_from_mirrors() -> Result<(), Box<dyn std::error::Error>> {
+        // The lib is only reachable through a mirror of its host
+        let mirror_dir = TempDir::new()?;
+        let lib_dir = mirror_dir.path().join("org").join("lib");
+        std::fs::create_dir_all(&lib_dir)?;
+        test_utils::create_git_repo_in_dir(&lib_dir, &PathBuf::from("README.md"), "lib").unwrap();
+        let lib_url = "https://example.invalid/org/lib";
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "{}", "branch": "main"}} }}"#,
+                lib_url
+            ),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let cache_dir = TempDir::new()?;
+        let user_config = workdir.child(".cranerc");
+        user_config.write_str(&format!(
+            r#"mirrors = {{"example.invalid": ["file:///nonexistent/mirror", "file://{}"]}}"#,
+            mirror_dir.path().display()
+        ))?;
+        let target_dir = "test_sync_fetches_from_mirrors";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .env("CRANE_CONFIG", user_config.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+        let lib = workdir.child(target_dir).child("lib");
+        lib.child("README.md").assert("lib");
+        // The remote keeps the url of the lib
+        let repo = Repository::open(lib.path())?;
+        assert_eq!(repo.find_remote("origin")?.url(), Some(lib_url));
+
+        // A rewrite of the workspace instead of the mirrors of the user
+        test_utils::modify_file_in_repo(
+            &lib_dir,
+            &PathBuf::from("README.md"),
+            "lib v2",
+            false,
+            true,
+            true,
+        )?;
+        workdir
+            .child(target_dir)
+            .child(".crane_workspace")
+            .child("config")
+            .write_str(&format!(
+                r#"url_rewrites = {{"https://example.invalid/": "file://{}/"}}"#,
+                mirror_dir.path().display()
+            ))?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .env("CRANE_CONFIG", workdir.child("missing").path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+        lib.child("README.md").assert("lib v2");
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_stops_when_interrupted() -> Result<(), Box<dyn std::error::Error>> {
+        let lib_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(lib_repo_dir.path(), &PathBuf::from("README.md"), "lib")
+            .unwrap();
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "file://{}/.git", "branch": "main"}} }}"#,
+                lib_repo_dir.path().display()
+            ),
+        )
+        .unwrap();
+        let main_url = format!("file://{}/.git", main_repo_dir.path().display());
+
+        // The solution waits for its cache repository while the sync is interrupted
+        let cache_dir = TempDir::new()?;
+        let cache_lock = cache_dir
+            .path()
+            .join("git")
+            .join(GitUrl::parse(&main_url)?.cache_key())
+            .with_extension("lock");
+        std::fs::create_dir_all(cache_lock.parent().unwrap())?;
+        let lock = FileLock::try_acquire(&cache_lock)?.unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_stops_when_interrupted";
+        let mut child = Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(&main_url)
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let mut stdout = String::new();
+        let mut read_until = |text: &str| -> std::io::Result<()> {
+            for line in lines.by_ref() {
+                let line = line?;
+                stdout.push_str(&line);
+                stdout.push('\n');
+                if line.contains(text) {
+                    return Ok(());
+                }
+            }
+            panic!("no line contains {}: {}", text, stdout);
+        };
+
+        read_until("Waiting for cache lock")?;
+        Command::new("kill")
+            .args(["-INT", &child.id().to_string()])
+            .status()?;
+        read_until("Interrupted")?;
+        drop(lock);
+
+        for line in lines {
+            stdout.push_str(&line?);
+            stdout.push('\n');
+        }
+        let output = child.wait()?;
+        assert!(!output.success(), "{}", stdout);
+        assert!(
+            stdout.contains("sync interrupted, lib left untouched"),
+            "{}",
+            stdout
+        );
+        // The solution in progress was finished, the lib was not started
+        workdir
+            .child(target_dir)
+            .child(".crane")
+            .assert(predicate::path::exists());
+        workdir
+            .child(target_dir)
+            .child("lib")
+            .assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_waits_for_workspace_lock() -> Result<(), Box<dyn std::error::Error>> {
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "main",
+        )
+        .unwrap();
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_waits_for_workspace_lock";
+        // The root of a clone holds the lock, it is removed when nothing could be cloned
+        let user_config = workdir.child("cranerc");
+        user_config.write_str("fetch_retries = 0")?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!(
+                "file://{}/missing/.git",
+                main_repo_dir.path().display()
+            ))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--no-cache")
+            .env("CRANE_CONFIG", user_config.path())
+            .current_dir(workdir)
+            .assert()
+            .failure();
+        workdir.child(target_dir).assert(predicate::path::missing());
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // Another sync is running
+        let lock = workdir
+            .child(target_dir)
+            .child(".crane_workspace")
+            .child("lock");
+        lock.write_str(&format!("{}\n0\ncrane sync\n", std::process::id()))?;
+        Command::cargo_bin("crane")?
+            .arg("status")
+            .arg(target_dir)
+            .current_dir(workdir)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains(format!(
+                "The workspace is in use by pid {} (crane sync)",
+                std::process::id()
+            )));
+
+        let mut child = Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--wait")
+            .current_dir(workdir)
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let mut stdout = String::new();
+        for line in lines.by_ref() {
+            let line = line?;
+            stdout.push_str(&line);
+            stdout.push('\n');
+            if line.contains("Waiting for the workspace") {
+                break;
+            }
+        }
+        assert!(stdout.contains("Waiting for the workspace"), "{}", stdout);
+        std::fs::remove_file(lock.path())?;
+        for line in lines {
+            stdout.push_str(&line?);
+            stdout.push('\n');
+        }
+        assert!(child.wait()?.success(), "{}", stdout);
+        lock.assert(predicate::path::missing());
+
+        // Readers do not create the files of the workspace
+        std::fs::remove_dir_all(workdir.child(target_dir).child(".crane_workspace").path())?;
+        Command::cargo_bin("crane")?
+            .arg("status")
+            .arg(target_dir)
+            .current_dir(workdir)
+            .assert()
+            .success();
+        workdir
+            .child(target_dir)
+            .child(".crane_workspace")
+            .assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_after_pushing_from_workspace() -> Result<(), Box<dyn std::error::Error>> {
+        // The lib is pushed to, so its remote is bare
+        let lib_source_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            lib_source_dir.path(),
+            &PathBuf::from("README.md"),
+            "lib",
+        )
+        .unwrap();
+        let lib_remote_dir = TempDir::new()?;
+        Command::new("git")
+            .args(["clone", "--bare", "--quiet"])
+            .arg(lib_source_dir.path())
+            .arg(lib_remote_dir.path())
+            .assert()
+            .success();
+        let main_repo_dir = TempDir::new()?;
+        test_utils::create_git_repo_in_dir(
+            main_repo_dir.path(),
+            &PathBuf::from(".crane"),
+            &format!(
+                r#"deps = {{ "lib": {{"type": "git", "url": "file://{}", "branch": "main"}} }}"#,
+                lib_remote_dir.path().display()
+            ),
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let cache_dir = TempDir::new()?;
+        let target_dir = "test_sync_after_pushing_from_workspace";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", main_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // The new tip of the lib is only in the workspace and its remote
+        let lib = workdir.child(target_dir).child("lib");
+        test_utils::modify_file_in_repo(
+            lib.path(),
+            &PathBuf::from("README.md"),
+            "lib v2",
+            false,
+            true,
+            true,
+        )?;
+        Command::new("git")
+            .args(["push", "--quiet", "origin", "HEAD:main"])
+            .current_dir(lib.path())
+            .assert()
+            .success();
+        let pushed = Repository::open(lib.path())?.head()?.target();
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--cache-dir")
+            .arg(cache_dir.path())
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("up to date"));
+        assert_eq!(Repository::open(lib.path())?.head()?.target(), pushed);
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_keeps_unpushed_commits() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_keeps_unpushed_commits";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // Commit locally while the remote moves on
+        test_utils::modify_file_in_repo(
+            &workdir.child(target_dir),
+            &PathBuf::from("LOCAL.md"),
+            "test",
+            true,
+            true,
+            true,
+        )?;
+        test_utils::modify_file_in_repo(
+            &source_repo_dir,
+            &PathBuf::from("README.2.md"),
+            "test",
+            true,
+            true,
+            true,
+        )?;
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .current_dir(workdir)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains("1 unpushed commit(s)"));
+        workdir
+            .child(target_dir)
+            .child("LOCAL.md")
+            .assert(predicate::path::exists());
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--force")
+            .current_dir(workdir)
+            .assert()
+            .success();
+        let source_head = Repository::open(source_repo_dir.path())?
+            .head()?
+            .target()
+            .unwrap();
+        let head = Repository::open(workdir.child(target_dir).path())?
+            .head()?
+            .target()
+            .unwrap();
+        assert_eq!(head, source_head);
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_with_strategies() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_with_strategies";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // Nothing local, the branch moves forward
+        test_utils::modify_file_in_repo(
+            &source_repo_dir,
+            &PathBuf::from("README.2.md"),
+            "test",
+            true,
+            true,
+            true,
+        )?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--strategy=ff-only")
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("fast-forwarded"));
+
+        // Local and upstream commits, only a rebase integrates them
+        test_utils::modify_file_in_repo(
+            &workdir.child(target_dir),
+            &PathBuf::from("LOCAL.md"),
+            "test",
+            true,
+            true,
+            true,
+        )?;
+        test_utils::modify_file_in_repo(
+            &source_repo_dir,
+            &PathBuf::from("README.3.md"),
+            "test",
+            true,
+            true,
+            true,
+        )?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--strategy=ff-only")
+            .current_dir(workdir)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains("diverged from upstream"));
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--strategy=rebase")
+            .current_dir(workdir)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("rebased 1 commits"));
+
+        let source_head = Repository::open(source_repo_dir.path())?
+            .head()?
+            .target()
+            .unwrap();
+        let repo = Repository::open(workdir.child(target_dir).path())?;
+        let head = repo.head()?.peel_to_commit()?;
+        assert_eq!(head.parent_id(0)?, source_head);
+        workdir
+            .child(target_dir)
+            .child("LOCAL.md")
+            .assert(predicate::path::exists());
+        workdir
+            .child(target_dir)
+            .child("README.3.md")
+            .assert(predicate::path::exists());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_rebase_conflict() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_rebase_conflict";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        // Both sides change the same line
+        test_utils::modify_file_in_repo(
+            &workdir.child(target_dir),
+            &PathBuf::from("README.md"),
+            "local",
+            false,
+            true,
+            true,
+        )?;
+        test_utils::modify_file_in_repo(
+            &source_repo_dir,
+            &PathBuf::from("README.md"),
+            "upstream",
+            false,
+            true,
+            true,
+        )?;
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .arg("--strategy=rebase")
+            .current_dir(workdir)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains(
+                "conflict left for manual resolution",
+            ));
+        workdir
+            .child(target_dir)
+            .child(".git/rebase-merge")
+            .assert(predicate::path::is_dir());
+
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn test_sync_creates_tracking_branch() -> Result<(), Box<dyn std::error::Error>> {
+        let source_repo_dir = TempDir::new()?;
+        let _ = test_utils::create_git_repo_in_dir(
+            &source_repo_dir.path(),
+            &PathBuf::from("README.md"),
+            "test",
+        )
+        .unwrap();
+
+        let workdir = &TempDir::new()?;
+        let target_dir = "test_sync_creates_tracking_branch";
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg("--url")
+            .arg(format!("file://{}/.git", source_repo_dir.path().display()))
+            .arg(target_dir)
+            .arg("--branch")
+            .arg("main")
+            .current_dir(workdir)
+            .assert()
+            .success();
+
+        let repo = Repository::open(workdir.child(target_dir).path())?;
+        assert_eq!(repo.head()?.name(), Some("refs/heads/main"));
+        let config = repo.config()?;
+        assert_eq!(config.get_string("branch.main.remote")?, "origin");
+        assert_eq!(config.get_string("branch.main.merge")?, "refs/heads/main");
+        assert!(repo.find_reference("refs/remotes/origin/main").is_ok());
+
+        // Go back to the layout of older versions
+        repo.find_reference("refs/heads/main")?
+            .rename("refs/for/main", false, "legacy")?;
+        repo.set_head_detached(repo.refname_to_id("refs/for/main")?)?;
+        test_utils::modify_file_in_repo(
+            &source_repo_dir,
+            &PathBuf::from("README.2.md"),
+            "test",
+            true,
+            true,
+            true,
+        )?;
+
+        Command::cargo_bin("crane")?
+            .arg("sync")
+            .arg(target_dir)
+            .current_dir(workdir)
+            .assert()
+            .success();
+        let repo = Repository::open(workdir.child(target_dir).path())?;
+        assert_eq!(repo.head()?.name(), Some("refs/heads/main"));
+        assert!(repo.find_reference("refs/for/main").is_err());
+        workdir
+            .child(target_dir)
+            .child("README.2.md")
+            .assert(predicate::path::exists());
+
+        Ok(())
+    }
+
     #[test(tokio::test)]
     async fn test_sync_simple_without_url_but_with_dir() -> Result<(), Box<dyn std::error::Error>> {
         // Create a source repository with 1 commit